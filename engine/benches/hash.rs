@@ -0,0 +1,80 @@
+//! Benchmarks for the rolling hash, which is the hot loop of fingerprinting.  Run with `cargo bench --bench hash`
+//!
+//! The previous 128-bit implementation (modulo the largest 64-bit prime) is included as a baseline
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use engine::hash::{kgram_hashes, HashParams, RollingHash};
+
+const K: usize = 25;
+
+fn test_input(len: usize) -> Vec<char> {
+    let mut x: u32 = 1;
+    (0..len).map(|_| {
+        x = x.wrapping_mul(1103515245).wrapping_add(12345);
+        char::from_u32('a' as u32 + (x >> 16) % 26).unwrap()
+    }).collect()
+}
+
+/// Hashes every k-gram using push/pop, the same way `fingerprint::get_fingerprint` does
+fn roll(chars: &[char], params: HashParams) -> u64 {
+    let mut h = RollingHash::with_params(K, params);
+    let mut acc = 0u64;
+    chars[..K].iter().for_each(|c| h.push_char(*c));
+    for (start, end) in chars.iter().zip(&chars[K..]) {
+        h.roll(*start, *end);
+        acc ^= h.get_u64();
+    }
+    acc
+}
+
+fn roll_u128_baseline(chars: &[char]) -> u64 {
+    const MODULUS: u128 = 18446744073709551557;
+    const BASE: u128 = 11842660086381224053;
+    let top = (0..K - 1).fold(1u128, |acc, _| acc * BASE % MODULUS);
+
+    let mut val = chars[..K].iter().fold(0u128, |acc, c| (acc * BASE + *c as u128 + 1) % MODULUS);
+    let mut acc = 0u64;
+    for (start, end) in chars.iter().zip(&chars[K..]) {
+        val = (val * BASE + *end as u128 + 1) % MODULUS;
+        val = (val + MODULUS - top * (*start as u128 + 1) % MODULUS) % MODULUS;
+        acc ^= val as u64;
+    }
+    acc
+}
+
+fn bench_rolling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rolling_hash");
+    for len in [1 << 12, 1 << 16, 1 << 20] {
+        let chars = test_input(len);
+        group.throughput(Throughput::Elements(len as u64));
+        group.bench_with_input(BenchmarkId::new("default", len), &chars, |b, chars| b.iter(|| roll(black_box(chars), HashParams::DEFAULT)));
+        group.bench_with_input(BenchmarkId::new("seeded", len), &chars, |b, chars| b.iter(|| roll(black_box(chars), HashParams::from_seed(1))));
+        group.bench_with_input(BenchmarkId::new("u128_baseline", len), &chars, |b, chars| b.iter(|| roll_u128_baseline(black_box(chars))));
+    }
+    group.finish();
+}
+
+/// All k-gram hashes of a multi-megabyte input, rolled one character at a time vs. in a batch
+fn bench_kgrams(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kgram_hashes");
+    group.sample_size(20);
+    for len in [1 << 20, 1 << 23] {
+        let chars = test_input(len);
+        group.throughput(Throughput::Elements(len as u64));
+        group.bench_with_input(BenchmarkId::new("rolling", len), &chars, |b, chars| b.iter(|| {
+            let mut h = RollingHash::new(K);
+            chars[..K].iter().for_each(|c| h.push(*c));
+            let mut ret = vec![h.get_u64()];
+            for (start, end) in chars.iter().zip(&chars[K..]) {
+                h.roll(*start, *end);
+                ret.push(h.get_u64());
+            }
+            ret
+        }));
+        group.bench_with_input(BenchmarkId::new("batch", len), &chars, |b, chars| b.iter(|| kgram_hashes(black_box(chars), K)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_rolling, bench_kgrams);
+criterion_main!(benches);
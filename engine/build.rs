@@ -0,0 +1,30 @@
+//! Compiles the tree-sitter grammars vendored in `vendor/` when the `ast` feature is enabled (see `preprocessor::ast`)
+
+#[cfg(feature = "ast")]
+fn compile_grammar(name: &str, scanner: bool) {
+    let src_dir = std::path::Path::new("vendor").join(format!("tree-sitter-{}", name)).join("src");
+
+    let mut build = cc::Build::new();
+    build.include(&src_dir)
+        .warnings(false)
+        .flag_if_supported("-Wno-unused-parameter")
+        .flag_if_supported("-Wno-unused-but-set-variable")
+        .flag_if_supported("-Wno-trigraphs")
+        .file(src_dir.join("parser.c"));
+    if scanner {
+        build.file(src_dir.join("scanner.c"));
+    }
+    build.compile(&format!("tree-sitter-{}", name));
+    println!("cargo:rerun-if-changed={}", src_dir.display());
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "ast")]
+    {
+        compile_grammar("cpp", true);
+        compile_grammar("java", false);
+        compile_grammar("python", true);
+    }
+}
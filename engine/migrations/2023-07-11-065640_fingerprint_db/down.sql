@@ -1,2 +0,0 @@
--- This file should undo anything in `up.sql`
-DROP TABLE fingerprints;
\ No newline at end of file
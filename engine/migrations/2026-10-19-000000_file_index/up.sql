@@ -0,0 +1,32 @@
+-- Databases made before migrations were run on open may already have these tables
+CREATE TABLE IF NOT EXISTS file_hashes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    path VARCHAR(64) NOT NULL, -- path of the file (relative to the fs)
+    sha256 CHAR(64) NOT NULL -- hash of the file's contents, to track when it needs updating
+);
+
+CREATE TABLE IF NOT EXISTS meta (
+    key VARCHAR(32) PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
+
+-- Each database belongs to one fs (it is named after the fs ID), so `fs_id` is dropped, and `path` is required.  Both the table created by
+-- the previous migration and the one older versions of `FingerprintDB::new` created have the remaining columns
+CREATE TABLE fingerprints_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    hash BIGINT UNSIGNED NOT NULL, -- hash of the fingerprint
+    loc INTEGER NOT NULL, -- location of the fingerprint inside the original file
+    lang VARCHAR(16) NOT NULL, -- language of the file (normalized file extension)
+    path VARCHAR(64) NOT NULL -- path of the file (relative to the fs)
+);
+INSERT INTO fingerprints_new (id, hash, loc, lang, path) SELECT id, hash, loc, lang, path FROM fingerprints WHERE path IS NOT NULL;
+DROP TABLE fingerprints;
+ALTER TABLE fingerprints_new RENAME TO fingerprints;
+
+-- Files whose fingerprints were dropped are fingerprinted again by the next refresh
+DELETE FROM file_hashes WHERE path NOT IN (SELECT path FROM fingerprints);
+DELETE FROM file_hashes WHERE id NOT IN (SELECT MAX(id) FROM file_hashes GROUP BY path);
+
+CREATE INDEX fingerprints_hash ON fingerprints (hash);
+CREATE INDEX fingerprints_path ON fingerprints (path);
+CREATE UNIQUE INDEX file_hashes_path ON file_hashes (path);
@@ -0,0 +1,679 @@
+//! Defines a database system for storing and matching fingerprint hashes.
+//! 
+//! The database system can efficiently query fingerprint hashes, along with quickly compute 
+//! 
+//! TODO: finish
+
+extern crate sqlite;
+extern crate sha2;
+extern crate hex;
+
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, sync::Mutex};
+
+use super::fs::{self, FS};
+use super::fingerprint::{self as fp, FingerprintParams, FingerprintSeq, FingerprintStrategy, KGramUnit};
+use super::hash::HashParams;
+use super::matching::{self, FrequencyLimit, Segment, SegmentMatch, SuppressedHash};
+use super::preprocessor::{self, langs::{self, Lang}, notebook::Notebook};
+use sha2::{Sha256, Digest};
+
+/// Enum containing the different types of messages that can be passed to workers
+enum IPCPacket {
+    // TODO: impl
+}
+
+pub struct Options {
+    fingerprint_params: FingerprintParams,
+    n_workers: i32,
+    /// Parameters used to hash fingerprints.  A database only accepts the parameters it was created with
+    hash_params: HashParams,
+    /// Strategy used to select fingerprints.  A database only accepts the strategy it was created with
+    strategy: Box<dyn FingerprintStrategy>,
+    /// Unit that the fingerprinting parameters are measured in.  A database only accepts the unit it was created with
+    unit: KGramUnit,
+    /// Limits for reading the archives found in the filesystem (see `FingerprintDB::refresh`)
+    archive_limits: fs::ArchiveLimits,
+    /// Segment pairs less similar than this are not reported (see `matching::match_segments`)
+    min_segment_similarity: f64,
+    /// Whether top-level definitions are sorted before fingerprinting (see `PreprocessorOptions::canonical`).  A database only accepts the
+    /// setting it was created with
+    canonical: bool,
+    /// Whether documents are preprocessed by their syntax tree (see `PreprocessorOptions::ast`).  A database only accepts the setting it was
+    /// created with
+    ast: bool,
+    /// Hashes found in more stored files than this are ignored when ranking matches (see `matching::FrequencyFilter`)
+    frequency_limit: Option<FrequencyLimit>,
+}
+
+impl Options {
+    /// Options with the given fingerprinting parameters, and the same hash parameters and strategy as `get_fingerprint`
+    pub fn new(fingerprint_params: FingerprintParams) -> Self {
+        Self::with_params(fingerprint_params, HashParams::DEFAULT, Box::new(fp::RobustWinnowing))
+    }
+
+    pub fn with_params(fingerprint_params: FingerprintParams, hash_params: HashParams, strategy: Box<dyn FingerprintStrategy>) -> Self {
+        Self { fingerprint_params, n_workers: 1, hash_params, strategy, unit: KGramUnit::Chars, archive_limits: fs::ArchiveLimits::default(),
+            min_segment_similarity: 0.5, canonical: false, ast: false, frequency_limit: None }
+    }
+
+    /// Sets whether documents are preprocessed by their syntax tree rather than by the char-level steps, for languages that have a grammar
+    /// (see `PreprocessorOptions::ast`)
+    #[cfg(feature = "ast")]
+    pub fn with_ast(mut self, ast: bool) -> Self {
+        self.ast = ast;
+        self
+    }
+
+    /// Sets whether top-level definitions are sorted into a canonical order before fingerprinting, so that reordering functions does not
+    /// break up matches (see `PreprocessorOptions::canonical`)
+    pub fn with_canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    /// Sets the unit that the fingerprinting parameters are measured in, instead of chars (see `get_token_fingerprint`)
+    pub fn with_unit(mut self, unit: KGramUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Sets how similar a pair of segments must be to be reported in `FileMatch::segments`, instead of 0.5
+    pub fn with_min_segment_similarity(mut self, min_segment_similarity: f64) -> Self {
+        self.min_segment_similarity = min_segment_similarity;
+        self
+    }
+
+    /// Sets how many stored files a hash can be found in before it is ignored by queries, like MOSS's `-m` option.  Such hashes are idioms
+    /// rather than evidence of copying, so they are left out of `FileMatch::shared`, `positions` and `segments`, and reported in
+    /// `FileMatch::suppressed` instead.  The limit is not recorded in the database, so it can differ between runs
+    pub fn with_frequency_limit(mut self, frequency_limit: FrequencyLimit) -> Self {
+        self.frequency_limit = Some(frequency_limit);
+        self
+    }
+
+    /// Sets the limits for reading archives, instead of `ArchiveLimits::default()`
+    pub fn with_archive_limits(mut self, archive_limits: fs::ArchiveLimits) -> Self {
+        self.archive_limits = archive_limits;
+        self
+    }
+}
+
+/// Returned when opening a database whose fingerprints were made differently (i.e. with different hash parameters), since its fingerprints
+/// cannot be compared with new ones.  `key` is the `meta` entry that differs
+#[derive(Debug)]
+pub struct MetadataMismatch {
+    pub key: &'static str,
+    pub stored: String,
+    pub expected: String,
+}
+
+impl std::fmt::Display for MetadataMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Database fingerprints were made with {} {}, but {} was given", self.key, self.stored, self.expected)
+    }
+}
+
+impl std::error::Error for MetadataMismatch {}
+
+/// Summary of the changes made by `FingerprintDB::refresh`.  Paths are relative to the root of the filesystem
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct RefreshReport {
+    /// Files that were not in the database, and have been fingerprinted
+    pub added: Vec<PathBuf>,
+    /// Files whose contents changed, and have been fingerprinted again
+    pub changed: Vec<PathBuf>,
+    /// Files that no longer exist, and whose fingerprints have been removed
+    pub removed: Vec<PathBuf>,
+    /// Files that cannot be fingerprinted (i.e. binary files, or text that is not UTF-8).  Their sha256 hashes are still stored, so they are
+    /// only skipped (and reported) again once they change
+    pub skipped: Vec<PathBuf>,
+    /// Number of files whose contents did not change
+    pub unchanged: usize,
+    /// Name of the strategy that selected the fingerprints (see `FingerprintStrategy::name`)
+    pub strategy: String,
+}
+
+/// A stored file that shares fingerprints with a query, as returned by `FingerprintDB::query`
+#[derive(Clone, PartialEq, Debug)]
+pub struct FileMatch {
+    /// Path of the stored file, relative to the root of the filesystem
+    pub path: PathBuf,
+    /// Language the stored file was preprocessed as, i.e. `Lang::Generic` for unrecognized text files
+    pub lang: Lang,
+    /// Number of distinct hashes shared with the query
+    pub shared: usize,
+    /// For each fingerprint of the query whose hash is in the stored file, the pair of (position in the query, first position of the hash in
+    /// the stored file), sorted by position in the query.  Only the first position is used so that a hash repeated in both files (i.e. a
+    /// repeated line) gives one pair per repetition in the query, rather than every combination
+    pub positions: Vec<(usize, usize)>,
+    /// For notebooks, the stored position of each pair in `positions` translated to (index of the cell in the notebook, line within the cell),
+    /// see `Notebook::locate`.  None for other files, or if the stored notebook can no longer be read
+    pub stored_cells: Option<Vec<(usize, usize)>>,
+    /// Same as `stored_cells`, for the query position of each pair.  Only set by `find_best_files`, when the queried file is a notebook
+    pub query_cells: Option<Vec<(usize, usize)>>,
+    /// Pairs of (function or class of the query, function or class of the stored file) that are at least as similar as set in `Options`,
+    /// from most to least similar (see `matching::match_segments`).  Only set by `find_best_files`, since segments are found in the source
+    /// of the query.  Empty if the stored file can no longer be read
+    pub segments: Vec<SegmentMatch>,
+    /// Hashes shared with the query that were ignored because they are found in too many stored files, from most to least common (see
+    /// `Options::with_frequency_limit`)
+    pub suppressed: Vec<SuppressedHash>,
+    /// Name of the strategy that selected the stored fingerprints (see `FingerprintStrategy::name`), since matches found with different
+    /// strategies are not comparable
+    pub strategy: String,
+}
+
+const DB_DIR_PATH: &'static str = ".db";
+
+/// A file of the filesystem that is used as a query, see `FingerprintDB::find_best_files`
+struct QueryFile {
+    /// The notebook that positions in the query refer to, if the file is one
+    notebook: Option<Notebook>,
+    segments: Vec<Segment>,
+}
+
+/// Finds the segments of a file.  For notebooks, they are found in the joined source of the code cells, which is what positions refer to
+fn segments_of(lang: Lang, contents: &str, notebook: Option<&Notebook>) -> Vec<Segment> {
+    matching::find_segments(lang, notebook.map_or(contents, Notebook::source))
+}
+
+/// A database handle for storing and matching hashes.
+/// 
+/// It connects to a SQLite database file based on the filesystem handle provided.
+/// The filesystem handle is used to generate a unique identifier for the database file,
+/// which is placed relative to DB_DIR_PATH
+pub struct FingerprintDB {
+    fs: Box<dyn fs::FS>,
+    conn: sqlite::Connection,
+
+    /// Options for the database
+    opts: Options,
+    /// Boolean value that contains values
+    force_quit: Mutex<bool>, 
+}
+
+fn get_db_path(fs: &dyn fs::FS) -> PathBuf {
+    let mut path_hasher = Sha256::new();
+    path_hasher.update(fs.get_identifier());
+    let result_str = hex::encode(path_hasher.finalize());
+    PathBuf::from(DB_DIR_PATH).join(result_str)
+}
+
+/// Schema migrations, in order.  The `user_version` of a database is the number of migrations that have been applied to it
+const MIGRATIONS: [&str; 2] = [
+    include_str!("../../migrations/2023-07-11-065640_fingerprint_db/up.sql"),
+    include_str!("../../migrations/2026-10-19-000000_file_index/up.sql"),
+];
+
+/// Returned when opening a database with migrations this version does not know about, i.e. one made by a newer version of the engine
+#[derive(Debug)]
+pub struct UnsupportedSchema {
+    pub version: usize,
+    pub supported: usize,
+}
+
+impl std::fmt::Display for UnsupportedSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Database schema version {} is newer than the latest supported version {}", self.version, self.supported)
+    }
+}
+
+impl std::error::Error for UnsupportedSchema {}
+
+/// Applies the migrations that have not been applied to a database yet.  Each migration runs in its own transaction, so a failed
+/// migration leaves the database at the previous version
+fn migrate(conn: &sqlite::Connection) -> Result<(), Box<dyn std::error::Error>> {
+    let version = {
+        let mut stmt = conn.prepare("PRAGMA user_version")?;
+        stmt.next()?;
+        stmt.read::<i64, _>(0)? as usize
+    };
+    if version > MIGRATIONS.len() {
+        return Err(Box::new(UnsupportedSchema { version, supported: MIGRATIONS.len() }));
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        conn.execute("BEGIN TRANSACTION")?;
+        let applied = conn.execute(migration).and_then(|_| conn.execute(format!("PRAGMA user_version = {}", i + 1)));
+        conn.execute(if applied.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+        applied?;
+        log::info!("Applied database migration {}", i + 1);
+    }
+
+    Ok(())
+}
+
+/// Checks the metadata recorded in a database against the expected (key, value) pairs, and records the keys that are missing.  Fails with
+/// `MetadataMismatch` if a recorded value differs, in which case nothing is changed.
+///
+/// Databases made before a key was recorded (i.e. before hash parameters were) hold fingerprints made with unknown settings, which cannot be
+/// compared with new ones.  So if a key is missing from a database that already holds files, its fingerprints and file hashes are removed,
+/// and the next refresh fingerprints every file again
+fn check_metadata(conn: &sqlite::Connection, expected: &[(&'static str, String)]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut missing = vec![];
+    let mut stmt = conn.prepare("SELECT value FROM meta WHERE key = :key")?;
+    for (key, value) in expected {
+        stmt.reset()?;
+        stmt.bind((":key", *key))?;
+        if stmt.next()? == sqlite::State::Row {
+            let stored = stmt.read::<String, _>("value")?;
+            if stored != *value {
+                return Err(Box::new(MetadataMismatch { key, stored, expected: value.clone() }));
+            }
+        }
+        else {
+            missing.push((*key, value.as_str()));
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    conn.execute("BEGIN TRANSACTION")?;
+    let recorded = (|| -> Result<(), sqlite::Error> {
+        let mut files = conn.prepare("SELECT EXISTS (SELECT 1 FROM fingerprints) OR EXISTS (SELECT 1 FROM file_hashes)")?;
+        files.next()?;
+        if files.read::<i64, _>(0)? != 0 {
+            log::warn!("Database has no recorded {}, so its fingerprints are removed and will be made again on refresh",
+                missing.iter().map(|(key, _)| *key).collect::<Vec<_>>().join(", "));
+            conn.execute("DELETE FROM fingerprints; DELETE FROM file_hashes;")?;
+        }
+
+        let mut insert = conn.prepare("INSERT INTO meta (key, value) VALUES (:key, :value)")?;
+        for (key, value) in &missing {
+            insert.reset()?;
+            insert.bind((":key", *key))?;
+            insert.bind((":value", *value))?;
+            insert.next()?;
+        }
+        Ok(())
+    })();
+    conn.execute(if recorded.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+    Ok(recorded?)
+}
+
+/// Reads the rows of a query on `suppressed_hashes`, from most to least common
+fn read_suppressed(stmt: &mut sqlite::Statement) -> Result<Vec<SuppressedHash>, sqlite::Error> {
+    let mut ret = vec![];
+    while stmt.next()? == sqlite::State::Row {
+        ret.push(SuppressedHash { hash: stmt.read::<i64, _>("hash")? as u64, documents: stmt.read::<i64, _>("documents")? as usize });
+    }
+    ret.sort_by(|x, y| y.documents.cmp(&x.documents).then(x.hash.cmp(&y.hash)));
+    Ok(ret)
+}
+
+impl FingerprintDB {
+    /// Construct a new db handle based on a filesystem handle.
+    /// On creation, the databse file will be initialized if it does not exist.
+    /// 
+    /// The schema is defined by the migrations in `migrations/`, which are applied on open to bring older databases up to date.
+    /// There are 3 database tables:
+    /// - `fingerprints`: stores the fingerprint hashes and their locations, indexed by hash (for `query`) and by path (for `refresh`)
+    /// - `file_hashes`: stores the sha256 hashes of files to track when they need updating
+    /// - `meta`: stores key-value metadata: the ID of the filesystem, the version of the hash parameters (see `HashParams::version`), the
+    ///   name of the selection strategy (see `FingerprintStrategy::name`), the k-gram unit (see `KGramUnit::name`), whether definitions
+    ///   were sorted (see `Options::with_canonical`) and whether the AST-based preprocessor was used (see `Options::with_ast`) when the
+    ///   fingerprints were made.  Fingerprints of databases that are missing any of these are removed (see `check_metadata`)
+    ///
+    /// Fails with `MetadataMismatch` if the database already holds fingerprints made with different hash parameters, a different strategy, a
+    /// different unit or different preprocessing settings, with `UnsupportedSchema` if it was made by a newer version of the engine, and with
+    /// `InvalidFraction` if the frequency limit is invalid
+    pub fn new(fs: Box<dyn fs::FS>, opts: Options) -> Result<FingerprintDB, Box<dyn std::error::Error>> {
+        if let Some(limit) = opts.frequency_limit {
+            limit.validate()?;
+        }
+
+        let db_path = get_db_path(fs.as_ref());
+        std::fs::create_dir_all(db_path.parent().unwrap())?; // db_path will be a file under some directory, so parent() is safe
+        let conn = sqlite::open(get_db_path(fs.as_ref()))?;
+        migrate(&conn)?;
+
+        conn.execute("CREATE TEMP TABLE IF NOT EXISTS query_fingerprints (
+            hash BIGINT UNSIGNED NOT NULL,
+            loc INTEGER NOT NULL
+        );")?;
+        conn.execute("CREATE TEMP TABLE IF NOT EXISTS suppressed_hashes (
+            hash BIGINT UNSIGNED PRIMARY KEY NOT NULL,
+            documents INTEGER NOT NULL
+        );")?;
+        check_metadata(&conn, &[
+            ("fs_id", fs.get_identifier()),
+            ("hash_params", opts.hash_params.version()),
+            ("strategy", opts.strategy.name()),
+            ("unit", opts.unit.name().to_string()),
+            ("canonical", opts.canonical.to_string()),
+            ("ast", opts.ast.to_string()),
+        ])?;
+
+        Ok(FingerprintDB {
+            fs, conn, opts,
+            force_quit: Mutex::new(false),
+        })
+    }
+
+    /// Preprocesses and fingerprints the contents of a file.  Returns None (with a logged reason) if the file cannot be fingerprinted, i.e. it
+    /// is binary (see `langs::resolve`) or not UTF-8.  Notebooks are returned as well, since their positions refer to the joined source of
+    /// their code cells
+    fn fingerprint_file(&self, path: &Path, contents: &[u8]) -> Option<(Lang, FingerprintSeq, Option<Notebook>)> {
+        let lang = langs::resolve(path, contents)?;
+        let Ok(contents) = std::str::from_utf8(contents) else {
+            log::warn!("Skipping {}: contents are not valid UTF-8", path.display());
+            return None;
+        };
+        let options = langs::PreprocessorOptions {
+            canonical: self.opts.canonical,
+            #[cfg(feature = "ast")]
+            ast: self.opts.ast,
+            ..Default::default()
+        };
+        let (seq, notebook) = match lang {
+            Lang::Notebook => match Notebook::parse(contents) {
+                Ok(notebook) => (notebook.preprocess(&options), Some(notebook)),
+                Err(e) => {
+                    log::warn!("Skipping {}: invalid notebook: {}", path.display(), e);
+                    return None;
+                },
+            },
+            _ => (preprocessor::apply(contents, &langs::get_preprocessor_with_options(lang, &options)), None),
+        };
+
+        let fingerprint = match self.opts.unit {
+            KGramUnit::Chars => fp::get_fingerprint_with_params(&seq, &self.opts.fingerprint_params, self.opts.hash_params, self.opts.strategy.as_ref()),
+            KGramUnit::Tokens => fp::get_token_fingerprint_with_params(&seq, &self.opts.fingerprint_params, self.opts.hash_params, self.opts.strategy.as_ref()),
+        };
+        Some((lang, fingerprint, notebook))
+    }
+
+    /// Reads a stored file again, to translate positions in its fingerprints and find its segments.  Returns None if it can no longer be read
+    fn read_stored(&self, path: &Path, lang: Lang) -> Option<(Option<Notebook>, Vec<Segment>)> {
+        let contents = String::from_utf8(self.read_bytes(path).ok()?).ok()?;
+        let notebook = match lang {
+            Lang::Notebook => Some(Notebook::parse(&contents).ok()?),
+            _ => None,
+        };
+        let segments = segments_of(lang, &contents, notebook.as_ref());
+        Some((notebook, segments))
+    }
+
+    /// Brings the database up to date with the filesystem.  Files are compared with the database by their sha256 hash, so only new and
+    /// changed files are fingerprinted, and the fingerprints of deleted files are removed.
+    ///
+    /// Archives (see `ArchiveFSHandle::is_archive`) are expanded, so that each one is stored as a submission: its files are stored under the
+    /// path of the archive (i.e. `alice.zip/src/main.cpp`), and can be matched against the files of other archives.  Archives inside
+    /// archives are not expanded.  An archive that cannot be read (i.e. it exceeds the limits set in `Options`) is skipped as a whole.
+    ///
+    /// All changes are made in a single transaction, so if an error occurs (i.e. a file cannot be read), the database is left unchanged
+    pub fn refresh(&self) -> Result<RefreshReport, Box<dyn std::error::Error>> {
+        let found_files = self.fs.list_dir(PathBuf::new().as_path(), true)?;
+
+        let mut stored = HashMap::new();
+        let mut stmt = self.conn.prepare("SELECT path, sha256 FROM file_hashes")?;
+        while stmt.next()? == sqlite::State::Row {
+            stored.insert(stmt.read::<String, _>("path")?, stmt.read::<String, _>("sha256")?);
+        }
+
+        self.conn.execute("BEGIN TRANSACTION")?;
+        match self.sync_files(found_files, stored) {
+            Ok(report) => {
+                self.conn.execute("COMMIT")?;
+                Ok(report)
+            },
+            Err(e) => {
+                self.conn.execute("ROLLBACK")?;
+                Err(e)
+            },
+        }
+    }
+
+    /// Does the work of `refresh` inside its transaction.  `stored` maps each path in `file_hashes` to its sha256 hash
+    fn sync_files(&self, found_files: Vec<PathBuf>, mut stored: HashMap<String, String>) -> Result<RefreshReport, Box<dyn std::error::Error>> {
+        let mut report = RefreshReport { strategy: self.opts.strategy.name(), ..Default::default() };
+        let mut delete_fingerprints = self.conn.prepare("DELETE FROM fingerprints WHERE path = :path")?;
+        let mut delete_file = self.conn.prepare("DELETE FROM file_hashes WHERE path = :path")?;
+        let mut insert_fingerprint = self.conn.prepare("INSERT INTO fingerprints (hash, loc, path, lang) VALUES (:hash, :loc, :path, :lang)")?;
+        let mut insert_file = self.conn.prepare("INSERT INTO file_hashes (path, sha256) VALUES (:path, :sha256)")?;
+
+        let mut delete = |path: &str| -> Result<(), sqlite::Error> {
+            for stmt in [&mut delete_fingerprints, &mut delete_file] {
+                stmt.reset()?;
+                stmt.bind((":path", path))?;
+                stmt.next()?;
+            }
+            Ok(())
+        };
+
+        // Syncs a single file, which is either in the filesystem or in one of its archives.  If `skip` is set, the file is skipped without
+        // trying to fingerprint it
+        let mut sync_file = |path: PathBuf, contents: &[u8], skip: bool, stored: &mut HashMap<String, String>, report: &mut RefreshReport|
+            -> Result<(), Box<dyn std::error::Error>> {
+            let path_str = path.to_string_lossy().into_owned();
+            let sha256 = hex::encode(Sha256::digest(contents));
+
+            let old_sha256 = stored.remove(&path_str);
+            if old_sha256.as_ref() == Some(&sha256) {
+                report.unchanged += 1;
+                return Ok(());
+            }
+            if old_sha256.is_some() {
+                delete(&path_str)?;
+            }
+
+            // Skipped files are recorded too, so that they are not skipped again until they change
+            insert_file.reset()?;
+            insert_file.bind((":path", path_str.as_str()))?;
+            insert_file.bind((":sha256", sha256.as_str()))?;
+            insert_file.next()?;
+
+            let Some((lang, fingerprint, _)) = self.fingerprint_file(&path, contents).filter(|_| !skip) else {
+                report.skipped.push(path);
+                return Ok(());
+            };
+            for (loc, hash) in fingerprint {
+                insert_fingerprint.reset()?;
+                insert_fingerprint.bind((":hash", hash as i64))?; // hashes are below 2^61, so they fit
+                insert_fingerprint.bind((":loc", loc as i64))?;
+                insert_fingerprint.bind((":path", path_str.as_str()))?;
+                insert_fingerprint.bind((":lang", lang.to_ext()))?;
+                insert_fingerprint.next()?;
+            }
+
+            if old_sha256.is_some() { report.changed.push(path) } else { report.added.push(path) }
+            Ok(())
+        };
+
+        for path in found_files {
+            let contents = self.fs.read_bytes(&path)?;
+            // An archive is only recorded as a file of its own when it cannot be read, in which case it is skipped like a binary file until it
+            // changes.  Once it can be read, that record is removed along with the files that no longer exist
+            let unreadable = || stored.get(path.to_string_lossy().as_ref()).is_some_and(|sha256| *sha256 == hex::encode(Sha256::digest(&contents)));
+            if !fs::ArchiveFSHandle::is_archive(&path) || unreadable() {
+                sync_file(path, &contents, false, &mut stored, &mut report)?;
+                continue;
+            }
+
+            match fs::ArchiveFSHandle::from_bytes(&path, &contents, &self.opts.archive_limits) {
+                Ok(archive) => for file in archive.list_dir(Path::new(""), true)? {
+                    let contents = archive.read_bytes(&file)?;
+                    sync_file(path.join(file), &contents, false, &mut stored, &mut report)?;
+                },
+                Err(e) => {
+                    log::warn!("Skipping {}: cannot read archive: {}", path.display(), e);
+                    sync_file(path, &contents, true, &mut stored, &mut report)?;
+                },
+            }
+        }
+
+        // Whatever is left in `stored` no longer exists
+        for path in stored.into_keys() {
+            delete(&path)?;
+            report.removed.push(PathBuf::from(path));
+        }
+
+        Ok(report)
+    }
+
+    /// Reads a file, which is either in the filesystem or in one of its archives (see `refresh`)
+    fn read_bytes(&self, path: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if self.fs.is_file(path) {
+            return Ok(self.fs.read_bytes(path)?);
+        }
+
+        let archive_path = path.ancestors().skip(1).find(|p| fs::ArchiveFSHandle::is_archive(p) && self.fs.is_file(p))
+            .ok_or_else(|| format!("{} not found", path.display()))?;
+        let archive = fs::ArchiveFSHandle::from_bytes(archive_path, &self.fs.read_bytes(archive_path)?, &self.opts.archive_limits)?;
+        Ok(archive.read_bytes(path.strip_prefix(archive_path)?)?)
+    }
+
+    /// Finds the `n` stored files that share the most distinct hashes with a fingerprint, from most to least shared.  Files sharing nothing
+    /// are not returned.
+    ///
+    /// The fingerprint should be made with the same options as the database (see `get_fingerprint_with_params` and
+    /// `get_token_fingerprint_with_params`)
+    pub fn query(&self, fingerprint: &FingerprintSeq, n: usize) -> Result<Vec<FileMatch>, Box<dyn std::error::Error>> {
+        self.query_excluding(fingerprint, n, None, None)
+    }
+
+    /// Same as `query`, but with the fingerprint of a file in the filesystem, or in one of its archives (see `refresh`).  The file itself is not
+    /// returned
+    pub fn find_best_files(&self, path: &Path, n: usize) -> Result<Vec<FileMatch>, Box<dyn std::error::Error>> {
+        let contents = self.read_bytes(path)?;
+        let Some((lang, fingerprint, notebook)) = self.fingerprint_file(path, &contents) else {
+            return self.query_excluding(&vec![], n, Some(&path.to_string_lossy()), None);
+        };
+        // The file was fingerprinted, so it is UTF-8
+        let segments = segments_of(lang, std::str::from_utf8(&contents)?, notebook.as_ref());
+        self.query_excluding(&fingerprint, n, Some(&path.to_string_lossy()), Some(&QueryFile { notebook, segments }))
+    }
+
+    /// Moves the hashes of the loaded query that are found in more stored files than the frequency limit from `query_fingerprints` to
+    /// `suppressed_hashes`, so that they are left out of matching.  Document frequencies are counted over the `hash` index, so only the
+    /// hashes of the query are looked at
+    fn suppress_common_hashes(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.conn.execute("DELETE FROM suppressed_hashes")?;
+        let Some(limit) = self.opts.frequency_limit else {
+            return Ok(());
+        };
+
+        let n_documents = match limit {
+            FrequencyLimit::Count(_) => 0, // unused
+            FrequencyLimit::Fraction(_) => {
+                let mut stmt = self.conn.prepare("SELECT COUNT(DISTINCT path) FROM fingerprints")?;
+                stmt.next()?;
+                stmt.read::<i64, _>(0)? as usize
+            },
+        };
+
+        self.conn.execute("BEGIN TRANSACTION")?;
+        let suppressed = (|| -> Result<(), sqlite::Error> {
+            let mut insert = self.conn.prepare("INSERT INTO suppressed_hashes (hash, documents)
+                SELECT f.hash, COUNT(DISTINCT f.path) AS documents
+                FROM (SELECT DISTINCT hash FROM query_fingerprints) q JOIN fingerprints f ON f.hash = q.hash
+                GROUP BY f.hash
+                HAVING documents > :max")?;
+            insert.bind((":max", i64::try_from(limit.max_documents(n_documents)).unwrap_or(i64::MAX)))?;
+            insert.next()?;
+            self.conn.execute("DELETE FROM query_fingerprints WHERE hash IN (SELECT hash FROM suppressed_hashes)")
+        })();
+        self.conn.execute(if suppressed.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+        Ok(suppressed?)
+    }
+
+    /// Does the work of `query` and `find_best_files`.  `query_file` is set when the query is a file, so that its positions can be translated
+    /// and its segments matched
+    fn query_excluding(&self, fingerprint: &FingerprintSeq, n: usize, exclude: Option<&str>, query_file: Option<&QueryFile>)
+        -> Result<Vec<FileMatch>, Box<dyn std::error::Error>> {
+        // The query is loaded into a temporary table, so that matching is a join on the `hash` index rather than a scan per hash
+        self.conn.execute("BEGIN TRANSACTION")?;
+        let loaded = (|| -> Result<(), sqlite::Error> {
+            self.conn.execute("DELETE FROM query_fingerprints")?;
+            let mut insert = self.conn.prepare("INSERT INTO query_fingerprints (hash, loc) VALUES (:hash, :loc)")?;
+            for (loc, hash) in fingerprint {
+                insert.reset()?;
+                insert.bind((":hash", *hash as i64))?;
+                insert.bind((":loc", *loc as i64))?;
+                insert.next()?;
+            }
+            Ok(())
+        })();
+        self.conn.execute(if loaded.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+        loaded?;
+        self.suppress_common_hashes()?;
+
+        let mut best = self.conn.prepare("SELECT f.path AS path, MAX(f.lang) AS lang, COUNT(DISTINCT f.hash) AS shared
+            FROM (SELECT DISTINCT hash FROM query_fingerprints) q JOIN fingerprints f ON f.hash = q.hash
+            WHERE f.path IS NOT :exclude
+            GROUP BY f.path
+            ORDER BY shared DESC, f.path
+            LIMIT :n")?;
+        best.bind((":exclude", exclude))?;
+        best.bind((":n", n as i64))?;
+
+        // Repeated hashes are collapsed on both sides before joining, since the join would otherwise grow with the product of the repetitions
+        let mut positions = self.conn.prepare("WITH first AS (
+                SELECT hash, MIN(loc) AS loc FROM fingerprints
+                WHERE path = :path AND hash IN (SELECT hash FROM query_fingerprints)
+                GROUP BY hash
+            )
+            SELECT q.loc AS query_loc, first.loc AS stored_loc
+            FROM query_fingerprints q JOIN first ON first.hash = q.hash
+            ORDER BY q.loc, first.loc")?;
+        let mut stored_fingerprint = self.conn.prepare("SELECT loc, hash FROM fingerprints
+            WHERE path = :path AND hash NOT IN (SELECT hash FROM suppressed_hashes)")?;
+        let mut suppressed = self.conn.prepare("SELECT hash, documents FROM suppressed_hashes
+            WHERE hash IN (SELECT hash FROM fingerprints WHERE path = :path)")?;
+        let suppressed_in_query = read_suppressed(&mut self.conn.prepare("SELECT hash, documents FROM suppressed_hashes")?)?
+            .into_iter().map(|s| s.hash).collect::<HashSet<_>>();
+        let fingerprint = fingerprint.iter().filter(|(_, h)| !suppressed_in_query.contains(h)).copied().collect::<FingerprintSeq>();
+
+        let mut ret = vec![];
+        while best.next()? == sqlite::State::Row {
+            let path = best.read::<String, _>("path")?;
+            let lang_name = best.read::<String, _>("lang")?;
+            let lang = Lang::from_normalized_ext(&lang_name).ok_or_else(|| format!("Unknown language {} stored for {}", lang_name, path))?;
+            positions.reset()?;
+            positions.bind((":path", path.as_str()))?;
+            let mut file_positions = vec![];
+            while positions.next()? == sqlite::State::Row {
+                file_positions.push((positions.read::<i64, _>("query_loc")? as usize, positions.read::<i64, _>("stored_loc")? as usize));
+            }
+
+            let path = PathBuf::from(path);
+            let (stored_notebook, stored_segments) = match lang == Lang::Notebook || query_file.is_some() {
+                true => self.read_stored(&path, lang).unwrap_or_default(),
+                false => Default::default(),
+            };
+
+            let mut segments = vec![];
+            if let Some(query_file) = query_file.filter(|_| !stored_segments.is_empty()) {
+                stored_fingerprint.reset()?;
+                stored_fingerprint.bind((":path", path.to_string_lossy().as_ref()))?;
+                let mut stored = vec![];
+                while stored_fingerprint.next()? == sqlite::State::Row {
+                    stored.push((stored_fingerprint.read::<i64, _>("loc")? as usize, stored_fingerprint.read::<i64, _>("hash")? as u64));
+                }
+                segments = matching::match_segments(&fingerprint, &query_file.segments, &stored, &stored_segments, self.opts.min_segment_similarity, None);
+            }
+
+            suppressed.reset()?;
+            suppressed.bind((":path", path.to_string_lossy().as_ref()))?;
+            let file_suppressed = read_suppressed(&mut suppressed)?;
+
+            ret.push(FileMatch {
+                stored_cells: stored_notebook.and_then(|nb| file_positions.iter().map(|p| nb.locate(p.1)).collect()),
+                query_cells: query_file.and_then(|q| q.notebook.as_ref()).and_then(|nb| file_positions.iter().map(|p| nb.locate(p.0)).collect()),
+                segments,
+                suppressed: file_suppressed,
+                path,
+                lang,
+                shared: best.read::<i64, _>("shared")? as usize,
+                positions: file_positions,
+                strategy: self.opts.strategy.name(),
+            });
+        }
+
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests;
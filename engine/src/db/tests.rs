@@ -0,0 +1,519 @@
+use super::*;
+use crate::matching::SegmentKind;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A local filesystem in a fresh temporary directory.  The directory and the database made for it are removed on drop
+struct TempFS {
+    root: PathBuf,
+}
+
+impl TempFS {
+    fn new(name: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!("{}_{}_{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed), name));
+        std::fs::create_dir_all(&root).unwrap();
+        Self { root }
+    }
+
+    fn handle(&self) -> fs::LocalFSHandle {
+        fs::LocalFSHandle::new(&self.root)
+    }
+
+    fn open(&self) -> FingerprintDB {
+        FingerprintDB::new(Box::new(self.handle()), Options::new(FingerprintParams::new(5, 10).unwrap())).unwrap()
+    }
+
+    fn write(&self, path: &str, contents: &[u8]) {
+        let path = self.root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn remove(&self, path: &str) {
+        std::fs::remove_file(self.root.join(path)).unwrap();
+    }
+}
+
+impl Drop for TempFS {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(get_db_path(&self.handle()));
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Builds the expected report of a refresh, with the paths sorted
+fn report(added: &[&str], changed: &[&str], removed: &[&str], skipped: &[&str], unchanged: usize) -> RefreshReport {
+    let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+    RefreshReport {
+        added: paths(added),
+        changed: paths(changed),
+        removed: paths(removed),
+        skipped: paths(skipped),
+        unchanged,
+        strategy: String::from("robust-winnowing"),
+    }
+}
+
+/// Refreshes the database, sorting the paths of the report since the filesystem lists files in no particular order
+fn refresh_sorted(db: &FingerprintDB) -> RefreshReport {
+    let mut ret = db.refresh().unwrap();
+    for paths in [&mut ret.added, &mut ret.changed, &mut ret.removed, &mut ret.skipped] {
+        paths.sort();
+    }
+    ret
+}
+
+fn count(db: &FingerprintDB, sql: &str) -> i64 {
+    count_in(&db.conn, sql)
+}
+
+fn count_in(conn: &sqlite::Connection, sql: &str) -> i64 {
+    let mut stmt = conn.prepare(sql).unwrap();
+    stmt.next().unwrap();
+    stmt.read::<i64, _>(0).unwrap()
+}
+
+const SOLVE: &[u8] = b"def solve(n):\n    total = 0\n    for i in range(n):\n        total += i * i\n    return total\n";
+const GREET: &[u8] = b"def greet(name):\n    print('hello ' + name)\n    print('goodbye ' + name)\n";
+
+#[test]
+fn refresh_incremental() {
+    let temp = TempFS::new("db_refresh");
+    temp.write("a.py", SOLVE);
+    temp.write("src/b.py", GREET);
+    temp.write("notes", b"x = 1; y = 2; z = x + y; print(z);");
+    temp.write("data.bin", b"\x00\x01\x02\x03binary");
+    temp.write("latin1.py", b"# caf\xe9\nprint(1)\n");
+    let db = temp.open();
+
+    assert_eq!(refresh_sorted(&db), report(&["a.py", "notes", "src/b.py"], &[], &[], &["data.bin", "latin1.py"], 0));
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM file_hashes"), 5);
+    assert_eq!(count(&db, "SELECT COUNT(DISTINCT path) FROM fingerprints"), 3);
+
+    // Skipped files are remembered, so nothing is read as changed
+    assert_eq!(refresh_sorted(&db), report(&[], &[], &[], &[], 5));
+
+    temp.remove("a.py");
+    temp.write("src/b.py", SOLVE);
+    temp.write("c.py", GREET);
+    temp.write("data.bin", b"\x00\x01\x02\x03more binary");
+    assert_eq!(refresh_sorted(&db), report(&["c.py"], &["src/b.py"], &["a.py"], &["data.bin"], 2));
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM fingerprints WHERE path = 'a.py'"), 0);
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM file_hashes WHERE path = 'a.py'"), 0);
+
+    // The changed file's old fingerprints are replaced, so it now matches what `a.py` was
+    let fingerprint = db.fingerprint_file(Path::new("a.py"), SOLVE).unwrap().1;
+    let matches = db.query(&fingerprint, 10).unwrap();
+    assert_eq!(matches.iter().map(|m| m.path.clone()).collect::<Vec<_>>(), vec![PathBuf::from("src/b.py")]);
+    assert_eq!(matches[0].shared, fingerprint.iter().map(|(_, h)| *h).collect::<std::collections::HashSet<_>>().len());
+
+    // A reopened database picks up where the last one left off
+    drop(db);
+    assert_eq!(refresh_sorted(&temp.open()), report(&[], &[], &[], &[], 5));
+}
+
+#[cfg(unix)]
+#[test]
+fn refresh_rollback() {
+    let temp = TempFS::new("db_rollback");
+    temp.write("a.py", SOLVE);
+    let db = temp.open();
+    assert_eq!(refresh_sorted(&db), report(&["a.py"], &[], &[], &[], 0));
+    let fingerprints = count(&db, "SELECT COUNT(*) FROM fingerprints");
+
+    // A dangling link is listed as a file, but cannot be read
+    temp.write("a.py", GREET);
+    temp.write("b.py", SOLVE);
+    std::os::unix::fs::symlink(temp.root.join("missing.py"), temp.root.join("broken.py")).unwrap();
+    assert!(db.refresh().is_err());
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM fingerprints"), fingerprints);
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM file_hashes"), 1);
+
+    // Nothing from the failed refresh was kept, so the next one sees every change again
+    temp.remove("broken.py");
+    assert_eq!(refresh_sorted(&db), report(&["b.py"], &["a.py"], &[], &[], 0));
+}
+
+/// Stores fingerprints for a file directly, so that queries can be tested with known hashes
+fn insert(db: &FingerprintDB, path: &str, fingerprint: &FingerprintSeq) {
+    let mut stmt = db.conn.prepare("INSERT INTO fingerprints (hash, loc, path, lang) VALUES (:hash, :loc, :path, 'py')").unwrap();
+    for (loc, hash) in fingerprint {
+        stmt.reset().unwrap();
+        stmt.bind((":hash", *hash as i64)).unwrap();
+        stmt.bind((":loc", *loc as i64)).unwrap();
+        stmt.bind((":path", path)).unwrap();
+        stmt.next().unwrap();
+    }
+}
+
+fn summary(matches: &[FileMatch]) -> Vec<(&str, usize)> {
+    matches.iter().map(|m| (m.path.to_str().unwrap(), m.shared)).collect()
+}
+
+#[test]
+fn query_ranking() {
+    let temp = TempFS::new("db_query");
+    let db = temp.open();
+    insert(&db, "x.py", &vec![(0, 1), (10, 2), (20, 3), (30, 4), (40, 2)]);
+    insert(&db, "y.py", &vec![(0, 2), (5, 1), (8, 8)]);
+    insert(&db, "z.py", &vec![(3, 3)]);
+    insert(&db, "a.py", &vec![(7, 4)]);
+    insert(&db, "w.py", &vec![(0, 9)]);
+
+    let query = vec![(0, 1), (5, 2), (9, 3), (12, 4), (15, 5)];
+    let matches = db.query(&query, 10).unwrap();
+    assert_eq!(summary(&matches), vec![("x.py", 4), ("y.py", 2), ("a.py", 1), ("z.py", 1)]); // ties go by path
+    assert_eq!(summary(&db.query(&query, 2).unwrap()), vec![("x.py", 4), ("y.py", 2)]);
+    assert!(db.query(&query, 0).unwrap().is_empty());
+    assert!(db.query(&vec![(0, 5)], 10).unwrap().is_empty());
+
+    assert_eq!(matches[0].positions, vec![(0, 0), (5, 10), (9, 20), (12, 30)]);
+    assert_eq!(matches[1].positions, vec![(0, 5), (5, 0)]);
+    assert_eq!(matches[0].lang, Lang::Python);
+    assert_eq!(matches[0].strategy, "robust-winnowing");
+}
+
+#[test]
+fn query_repeated_hashes() {
+    let temp = TempFS::new("db_query_repeated");
+    let db = temp.open();
+    // i.e. the same line repeated throughout both files
+    insert(&db, "x.py", &(0..2000).map(|i| (i * 3 + 1, 7)).collect());
+
+    let query = (0..2000).map(|i| (i * 2, 7)).collect::<FingerprintSeq>();
+    let matches = db.query(&query, 10).unwrap();
+    assert_eq!(summary(&matches), vec![("x.py", 1)]);
+    assert_eq!(matches[0].positions, (0..2000).map(|i| (i * 2, 1)).collect::<Vec<_>>());
+}
+
+#[test]
+fn query_frequency_limit() {
+    let temp = TempFS::new("db_frequency");
+    let open = |limit| FingerprintDB::new(Box::new(temp.handle()), Options::new(FingerprintParams::new(5, 10).unwrap()).with_frequency_limit(limit)).unwrap();
+    let db = open(FrequencyLimit::Count(2));
+    // 1 is in every file (i.e. the signature of `main`), and 2 is in two of them
+    insert(&db, "x.py", &vec![(0, 1), (10, 2), (20, 3)]);
+    insert(&db, "y.py", &vec![(0, 1), (5, 2)]);
+    insert(&db, "z.py", &vec![(0, 1), (8, 4)]);
+    insert(&db, "w.py", &vec![(0, 1)]);
+
+    // w.py only shares the idiom, so it is not a match at all
+    let query = vec![(0, 1), (5, 2), (9, 3), (12, 4)];
+    let matches = db.query(&query, 10).unwrap();
+    assert_eq!(summary(&matches), vec![("x.py", 2), ("y.py", 1), ("z.py", 1)]);
+    assert_eq!(matches[0].positions, vec![(5, 10), (9, 20)]);
+    assert_eq!(matches[0].suppressed, vec![SuppressedHash { hash: 1, documents: 4 }]);
+
+    // Fractions are of the number of stored files, the same way as for `FrequencyFilter`
+    let matches = open(FrequencyLimit::Fraction(0.25)).query(&query, 10).unwrap();
+    assert_eq!(summary(&matches), vec![("x.py", 1), ("z.py", 1)]);
+    assert_eq!(matches[0].suppressed, vec![SuppressedHash { hash: 1, documents: 4 }, SuppressedHash { hash: 2, documents: 2 }]);
+    assert_eq!(summary(&open(FrequencyLimit::Fraction(1.0)).query(&query, 10).unwrap())[0], ("x.py", 3));
+    assert!(temp.open().query(&query, 10).unwrap()[0].suppressed.is_empty());
+
+    let err = FingerprintDB::new(Box::new(temp.handle()), Options::new(FingerprintParams::new(5, 10).unwrap()).with_frequency_limit(FrequencyLimit::Fraction(-1.0))).err().unwrap();
+    assert!(err.downcast_ref::<matching::InvalidFraction>().is_some());
+}
+
+#[test]
+fn find_best_segments_frequency_limit() {
+    let temp = TempFS::new("db_segments_frequency");
+    temp.write("a.py", &[SOLVE, GREET].concat());
+    temp.write("b.py", &[SOLVE, GREET].concat());
+    temp.write("c.py", GREET);
+    temp.write("d.py", GREET);
+    let db = FingerprintDB::new(Box::new(temp.handle()), Options::new(FingerprintParams::new(5, 10).unwrap()).with_frequency_limit(FrequencyLimit::Count(2))).unwrap();
+    db.refresh().unwrap();
+
+    // `greet` is in every file, so its hashes do not make it match
+    let matches = db.find_best_files(Path::new("a.py"), 10).unwrap();
+    assert_eq!(summary(&matches)[0].0, "b.py");
+    let segments = matches[0].segments.iter().map(|m| (m.a.name.as_str(), m.b.name.as_str())).collect::<Vec<_>>();
+    assert_eq!(segments, vec![("solve", "solve")]);
+    assert!(!matches[0].suppressed.is_empty());
+    assert!(matches[0].suppressed.iter().all(|s| s.documents == 4));
+}
+
+#[test]
+fn find_best_files() {
+    let temp = TempFS::new("db_best");
+    temp.write("a.py", SOLVE);
+    temp.write("copy.py", SOLVE);
+    temp.write("b.py", GREET);
+    let db = temp.open();
+    db.refresh().unwrap();
+
+    // The file itself is not a match, even though it shares every hash
+    let matches = db.find_best_files(Path::new("a.py"), 10).unwrap();
+    let distinct = db.fingerprint_file(Path::new("a.py"), SOLVE).unwrap().1.iter().map(|(_, h)| *h).collect::<std::collections::HashSet<_>>();
+    assert_eq!(summary(&matches), vec![("copy.py", distinct.len())]);
+    assert!(matches[0].positions.iter().all(|(q, f)| q == f));
+}
+
+/// Writes a zip archive with the given files
+fn write_zip(temp: &TempFS, path: &str, files: &[(&str, &[u8])]) {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    for (file, contents) in files {
+        writer.start_file(*file, zip::write::FileOptions::default()).unwrap();
+        std::io::Write::write_all(&mut writer, contents).unwrap();
+    }
+    temp.write(path, &writer.finish().unwrap().into_inner());
+}
+
+#[test]
+fn refresh_archives() {
+    let temp = TempFS::new("db_archives");
+    write_zip(&temp, "alice.zip", &[("main.py", SOLVE), ("lib/greet.py", GREET)]);
+    write_zip(&temp, "bob.zip", &[("solution/main.py", SOLVE)]);
+    temp.write("broken.zip", b"PK\x03\x04 not really a zip");
+    temp.write("carol.py", GREET);
+    let db = temp.open();
+
+    // Every archive is a submission, with its files stored under the path of the archive
+    assert_eq!(refresh_sorted(&db), report(&["alice.zip/lib/greet.py", "alice.zip/main.py", "bob.zip/solution/main.py", "carol.py"], &[], &[], &["broken.zip"], 0));
+    assert_eq!(refresh_sorted(&db), report(&[], &[], &[], &[], 5));
+
+    // Files in archives are matched against each other, and can be queried like any other file
+    let distinct = db.fingerprint_file(Path::new("main.py"), SOLVE).unwrap().1.iter().map(|(_, h)| *h).collect::<std::collections::HashSet<_>>();
+    assert_eq!(summary(&db.find_best_files(Path::new("alice.zip/main.py"), 10).unwrap()), vec![("bob.zip/solution/main.py", distinct.len())]);
+    assert_eq!(db.find_best_files(Path::new("carol.py"), 10).unwrap()[0].path, Path::new("alice.zip/lib/greet.py"));
+    assert!(db.find_best_files(Path::new("alice.zip/missing.py"), 10).is_err());
+
+    // Changing an archive changes its files, and an archive that can be read again replaces its record
+    write_zip(&temp, "alice.zip", &[("main.py", GREET)]);
+    write_zip(&temp, "broken.zip", &[("main.py", SOLVE)]);
+    assert_eq!(refresh_sorted(&db), report(&["broken.zip/main.py"], &["alice.zip/main.py"], &["alice.zip/lib/greet.py", "broken.zip"], &[], 2));
+
+    // Limits apply to every archive
+    let limits = fs::ArchiveLimits { max_entries: 1, ..Default::default() };
+    let options = Options::new(FingerprintParams::new(5, 10).unwrap()).with_archive_limits(limits);
+    write_zip(&temp, "bob.zip", &[("a.py", SOLVE), ("b.py", GREET)]);
+    let db = FingerprintDB::new(Box::new(temp.handle()), options).unwrap();
+    assert_eq!(refresh_sorted(&db), report(&[], &[], &["bob.zip/solution/main.py"], &["bob.zip"], 3));
+}
+
+#[test]
+fn find_best_segments() {
+    let temp = TempFS::new("db_segments");
+    temp.write("a.py", &[SOLVE, GREET].concat());
+    let work = std::str::from_utf8(SOLVE).unwrap().replace("solve", "work");
+    temp.write("b.py", format!("def count(xs):\n    return len([x for x in xs if x > 0])\n{}", work).as_bytes());
+    let db = temp.open();
+    db.refresh().unwrap();
+
+    // Only the copied function matches, and matches between segments are reported by name
+    let matches = db.find_best_files(Path::new("a.py"), 10).unwrap();
+    let segments = matches[0].segments.iter().map(|m| (m.a.name.as_str(), m.b.name.as_str(), m.a.kind)).collect::<Vec<_>>();
+    assert_eq!(segments, vec![("solve", "work", SegmentKind::Function)]);
+
+    // Queries by fingerprint have no source to find segments in
+    let query = db.fingerprint_file(Path::new("a.py"), SOLVE).unwrap().1;
+    assert!(db.query(&query, 10).unwrap()[0].segments.is_empty());
+
+    let db = FingerprintDB::new(Box::new(temp.handle()), Options::new(FingerprintParams::new(5, 10).unwrap()).with_min_segment_similarity(1.1)).unwrap();
+    assert!(db.find_best_files(Path::new("a.py"), 10).unwrap()[0].segments.is_empty());
+}
+
+#[test]
+fn find_best_notebooks() {
+    let cell = |kind: &str, source: &[u8]| format!(r#"{{"cell_type": "{}", "metadata": {{}}, "source": {}}}"#, kind, serde_json::Value::from(std::str::from_utf8(source).unwrap()));
+    let notebook = |cells: Vec<String>| format!(r#"{{"cells": [{}], "metadata": {{}}, "nbformat": 4, "nbformat_minor": 5}}"#, cells.join(", "));
+    let temp = TempFS::new("db_notebooks");
+    temp.write("a.ipynb", notebook(vec![cell("code", SOLVE)]).as_bytes());
+    temp.write("b.ipynb", notebook(vec![cell("markdown", b"# Notes"), cell("code", b"x = 1"), cell("code", SOLVE)]).as_bytes());
+    temp.write("c.py", SOLVE);
+    let db = temp.open();
+    db.refresh().unwrap();
+
+    let mut matches = db.find_best_files(Path::new("a.ipynb"), 10).unwrap();
+    matches.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(matches.iter().map(|m| (m.path.to_str().unwrap(), m.lang)).collect::<Vec<_>>(), vec![("b.ipynb", Lang::Notebook), ("c.py", Lang::Python)]);
+
+    // Positions in notebooks are translated to (cell, line), and the solution is the third cell of `b.ipynb`
+    let cells = |cells: &Option<Vec<(usize, usize)>>| cells.as_ref().unwrap().iter().map(|c| c.0).collect::<std::collections::HashSet<_>>();
+    assert_eq!(cells(&matches[0].stored_cells), [2].into());
+    assert_eq!(cells(&matches[0].query_cells), [0].into());
+    assert_eq!(matches[1].stored_cells, None);
+    assert_eq!(matches[1].query_cells.as_ref().map(Vec::len), Some(matches[1].positions.len()));
+    assert!(matches[0].stored_cells.as_ref().unwrap().iter().zip(matches[0].query_cells.as_ref().unwrap()).all(|(s, q)| s.1 == q.1));
+    assert_eq!(matches[0].segments.iter().map(|m| (m.a.name.as_str(), m.b.name.as_str())).collect::<Vec<_>>(), vec![("solve", "solve")]);
+
+    // Plain queries have no notebook to translate their positions with
+    let query = db.fingerprint_file(Path::new("c.py"), SOLVE).unwrap().1;
+    assert!(db.query(&query, 10).unwrap().iter().all(|m| m.query_cells.is_none()));
+}
+
+/// Creates a database for a filesystem the way older versions did, before it is opened
+fn create_legacy(temp: &TempFS, sql: &str) {
+    let path = get_db_path(&temp.handle());
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    sqlite::open(path).unwrap().execute(sql).unwrap();
+}
+
+fn user_version(db: &FingerprintDB) -> i64 {
+    count(db, "PRAGMA user_version")
+}
+
+#[test]
+fn migrate_legacy_schema() {
+    // Made by `FingerprintDB::new` before migrations ran on open: `path` is nullable, `file_hashes` can have duplicate paths, and the hash
+    // index already exists
+    let temp = TempFS::new("db_legacy");
+    temp.write("a.py", SOLVE);
+    create_legacy(&temp, &format!("
+        CREATE TABLE fingerprints (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            hash BIGINT UNSIGNED NOT NULL,
+            loc INTEGER NOT NULL,
+            path VARCHAR(64),
+            lang VARCHAR(16) NOT NULL
+        );
+        CREATE TABLE file_hashes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            path VARCHAR(64) NOT NULL,
+            sha256 CHAR(64) NOT NULL
+        );
+        CREATE TABLE meta (
+            key VARCHAR(32) PRIMARY KEY NOT NULL,
+            value TEXT NOT NULL
+        );
+        CREATE INDEX fingerprints_hash ON fingerprints (hash);
+        INSERT INTO fingerprints (hash, loc, path, lang) VALUES (1, 0, 'a.py', 'py'), (2, 5, 'a.py', 'py'), (3, 0, NULL, 'py'), (4, 2, 'b.py', 'py');
+        INSERT INTO file_hashes (path, sha256) VALUES ('a.py', 'stale'), ('b.py', 'b'), ('a.py', '{}'), ('c.py', 'c');
+        INSERT INTO meta (key, value) VALUES ('strategy', 'robust-winnowing');
+    ", hex::encode(Sha256::digest(SOLVE))));
+
+    let conn = sqlite::open(get_db_path(&temp.handle())).unwrap();
+    migrate(&conn).unwrap();
+    assert_eq!(count_in(&conn, "PRAGMA user_version"), 2);
+    assert_eq!(count_in(&conn, "SELECT COUNT(*) FROM fingerprints"), 3);
+    assert_eq!(count_in(&conn, "SELECT COUNT(*) FROM fingerprints WHERE path IS NULL"), 0);
+    assert_eq!(count_in(&conn, "SELECT COUNT(*) FROM file_hashes"), 2); // the latest hash of `a.py`, and `b.py`; `c.py` had no fingerprints
+    assert_eq!(count_in(&conn, "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name IN ('fingerprints_hash', 'fingerprints_path', 'file_hashes_path')"), 3);
+    drop(conn);
+
+    // The hash parameters (among others) the fingerprints were made with were never recorded, so they are removed on open instead of being
+    // compared with new ones, and every file is fingerprinted again
+    let db = temp.open();
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM fingerprints"), 0);
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM file_hashes"), 0);
+    assert!(db.query(&vec![(0, 1), (1, 2), (2, 4)], 10).unwrap().is_empty());
+    assert_eq!(refresh_sorted(&db), report(&["a.py"], &[], &[], &[], 0));
+    drop(db);
+
+    // The metadata is complete now, so the next open keeps the fingerprints
+    let db = temp.open();
+    assert_eq!(user_version(&db), 2);
+    assert_eq!(refresh_sorted(&db), report(&[], &[], &[], &[], 1));
+
+    // The same goes for keys added in later versions
+    db.conn.execute("DELETE FROM meta WHERE key = 'ast'").unwrap();
+    drop(db);
+    let db = temp.open();
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM fingerprints"), 0);
+    assert_eq!(refresh_sorted(&db), report(&["a.py"], &[], &[], &[], 0));
+}
+
+#[test]
+fn migrate_diesel_schema() {
+    // Made by the diesel migration, which had an `fs_id` column and no other tables
+    let temp = TempFS::new("db_diesel");
+    create_legacy(&temp, "
+        CREATE TABLE fingerprints (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            hash BIGINT UNSIGNED NOT NULL,
+            loc INTEGER NOT NULL,
+            lang VARCHAR(16) NOT NULL,
+            path VARCHAR(64) NOT NULL,
+            fs_id VARCHAR(64) NOT NULL
+        );
+        INSERT INTO fingerprints (hash, loc, lang, path, fs_id) VALUES (1, 0, 'java', 'A.java', 'fs');
+    ");
+
+    let conn = sqlite::open(get_db_path(&temp.handle())).unwrap();
+    migrate(&conn).unwrap();
+    assert_eq!(count_in(&conn, "PRAGMA user_version"), 2);
+    assert_eq!(count_in(&conn, "SELECT COUNT(*) FROM fingerprints WHERE path = 'A.java' AND lang = 'java'"), 1);
+    assert_eq!(count_in(&conn, "SELECT COUNT(*) FROM file_hashes"), 0);
+    drop(conn);
+
+    // Nothing was recorded about how the fingerprints were made, so they are removed on open
+    let db = temp.open();
+    assert_eq!(user_version(&db), 2);
+    assert!(db.query(&vec![(3, 1)], 10).unwrap().is_empty());
+}
+
+#[test]
+fn migrate_newer_schema() {
+    let temp = TempFS::new("db_newer");
+    create_legacy(&temp, "PRAGMA user_version = 3;");
+
+    let err = FingerprintDB::new(Box::new(temp.handle()), Options::new(FingerprintParams::new(5, 10).unwrap())).err().unwrap();
+    let unsupported = err.downcast_ref::<UnsupportedSchema>().unwrap();
+    assert_eq!((unsupported.version, unsupported.supported), (3, MIGRATIONS.len()));
+}
+
+#[test]
+fn options_recorded() {
+    let temp = TempFS::new("db_options");
+    temp.write("a.py", SOLVE);
+    temp.write("b.py", SOLVE);
+    let tokens = || Options::new(FingerprintParams::new(3, 5).unwrap()).with_unit(KGramUnit::Tokens);
+    let db = FingerprintDB::new(Box::new(temp.handle()), tokens()).unwrap();
+    db.refresh().unwrap();
+
+    // Token fingerprints are made the same way as `get_token_fingerprint_with_params`
+    let seq = preprocessor::apply(std::str::from_utf8(SOLVE).unwrap(), &langs::get_preprocessor(Lang::Python));
+    let expected = fp::get_token_fingerprint_with_params(&seq, &FingerprintParams::new(3, 5).unwrap(), HashParams::DEFAULT, &fp::RobustWinnowing);
+    assert_eq!(db.fingerprint_file(Path::new("a.py"), SOLVE).unwrap().1, expected);
+    assert_eq!(summary(&db.find_best_files(Path::new("a.py"), 10).unwrap())[0].0, "b.py");
+    drop(db);
+
+    // Char fingerprints cannot be mixed with token fingerprints
+    let err = FingerprintDB::new(Box::new(temp.handle()), Options::new(FingerprintParams::new(3, 5).unwrap())).err().unwrap();
+    let mismatch = err.downcast_ref::<MetadataMismatch>().unwrap();
+    assert_eq!((mismatch.key, mismatch.stored.as_str(), mismatch.expected.as_str()), ("unit", "tokens", "chars"));
+    assert!(FingerprintDB::new(Box::new(temp.handle()), tokens()).is_ok());
+    let err = FingerprintDB::new(Box::new(temp.handle()), tokens().with_canonical(true)).err().unwrap();
+    assert_eq!(err.downcast_ref::<MetadataMismatch>().unwrap().key, "canonical");
+}
+
+#[test]
+fn refresh_canonical() {
+    let temp = TempFS::new("db_canonical");
+    temp.write("a.py", &[SOLVE, GREET].concat());
+    temp.write("b.py", &[GREET, SOLVE].concat());
+    let db = FingerprintDB::new(Box::new(temp.handle()), Options::new(FingerprintParams::new(5, 10).unwrap()).with_canonical(true)).unwrap();
+    db.refresh().unwrap();
+
+    // With definitions sorted, swapping two functions gives the same fingerprint hashes
+    let hashes = |path: &str| {
+        let contents = std::fs::read(temp.root.join(path)).unwrap();
+        db.fingerprint_file(Path::new(path), &contents).unwrap().1.into_iter().map(|(_, h)| h).collect::<Vec<_>>()
+    };
+    assert_eq!(hashes("a.py"), hashes("b.py"));
+    assert_eq!(db.find_best_files(Path::new("a.py"), 10).unwrap()[0].shared, hashes("a.py").into_iter().collect::<std::collections::HashSet<_>>().len());
+}
+
+#[cfg(feature = "ast")]
+#[test]
+fn refresh_ast() {
+    let temp = TempFS::new("db_ast");
+    temp.write("a.py", SOLVE);
+    temp.write("b.py", b"def work(m):\n    acc = 0\n    for j in range(m):\n        acc += (j * j)\n    return acc\n");
+    let options = || Options::new(FingerprintParams::new(3, 5).unwrap()).with_ast(true);
+    let db = FingerprintDB::new(Box::new(temp.handle()), options()).unwrap();
+    db.refresh().unwrap();
+
+    // The redundant parentheses only differ at the char level
+    let hashes = |path: &str| {
+        let contents = std::fs::read(temp.root.join(path)).unwrap();
+        db.fingerprint_file(Path::new(path), &contents).unwrap().1.into_iter().map(|(_, h)| h).collect::<Vec<_>>()
+    };
+    assert_eq!(hashes("a.py"), hashes("b.py"));
+    assert_eq!(summary(&db.find_best_files(Path::new("a.py"), 10).unwrap())[0].0, "b.py");
+    drop(db);
+
+    let err = FingerprintDB::new(Box::new(temp.handle()), options().with_ast(false)).err().unwrap();
+    assert_eq!(err.downcast_ref::<MetadataMismatch>().unwrap().key, "ast");
+}
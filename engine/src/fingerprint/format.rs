@@ -0,0 +1,193 @@
+//! A compact binary encoding of fingerprints, so that they can be cached on disk, shipped between machines, and read by external tools.
+//!
+//! All integers are unsigned LEB128 varints unless stated otherwise, and strings are a varint byte length followed by UTF-8.  A file is:
+//!
+//! | Field           | Encoding                                                                                    |
+//! |-----------------|---------------------------------------------------------------------------------------------|
+//! | magic           | the 4 bytes `MFP\0`                                                                         |
+//! | format version  | 1 byte, currently 2                                                                         |
+//! | language        | 1 byte: 0 = Java, 1 = C/C++, 2 = Python, 3 = Jupyter notebook, 4 = text, 5 = generic        |
+//! | k-gram unit     | 1 byte: 0 = chars, 1 = tokens, see `KGramUnit`.  Missing in version 1, which only had chars |
+//! | k, t            | varints                                                                                     |
+//! | hash version    | string, see `HashParams::version`                                                           |
+//! | strategy        | string, see `FingerprintStrategy::name`                                                     |
+//! | source sha256   | 32 bytes, the hash of the document that was fingerprinted                                   |
+//! | count           | varint, the number of fingerprints                                                          |
+//! | fingerprints    | `count` pairs of (position delta, hash)                                                     |
+//!
+//! Position deltas are relative to the previous fingerprint's position (or 0 for the first one), and are zigzag-encoded since positions can
+//! decrease (i.e. after `steps::Reorder`).
+
+use std::io::{self, Read, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::hash::HashParams;
+use crate::preprocessor::langs::Lang;
+
+use super::{FingerprintParams, FingerprintSeq, FingerprintStrategy, KGramUnit};
+
+const MAGIC: &[u8; 4] = b"MFP\0";
+const FORMAT_VERSION: u8 = 2;
+/// Strings in the header are short, so anything longer means the file is corrupt
+const MAX_STRING_LEN: u64 = 1 << 12;
+
+/// Describes how a fingerprint was made, and from which document
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FingerprintHeader {
+    pub lang: Lang,
+    pub unit: KGramUnit,
+    pub params: FingerprintParams,
+    pub hash_version: String,
+    pub strategy: String,
+    pub source_sha256: [u8; 32],
+}
+
+impl FingerprintHeader {
+    pub fn new(lang: Lang, unit: KGramUnit, params: FingerprintParams, hash_params: HashParams, strategy: &dyn FingerprintStrategy, source: &str) -> Self {
+        Self {
+            lang,
+            unit,
+            params,
+            hash_version: hash_params.version(),
+            strategy: strategy.name(),
+            source_sha256: Sha256::digest(source).into(),
+        }
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn lang_tag(lang: Lang) -> u8 {
+    match lang {
+        Lang::Java => 0,
+        Lang::Cpp => 1,
+        Lang::Python => 2,
+        Lang::Notebook => 3,
+        Lang::Text => 4,
+        Lang::Generic => 5,
+    }
+}
+
+fn lang_from_tag(tag: u8) -> Option<Lang> {
+    [Lang::Java, Lang::Cpp, Lang::Python, Lang::Notebook, Lang::Text, Lang::Generic].get(tag as usize).copied()
+}
+
+fn write_varint(w: &mut impl Write, mut v: u64) -> io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (v & 0x7F) as u8;
+        v >>= 7;
+        if v == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    w.write_all(&buf[..len])
+}
+
+fn read_varint(r: &mut impl Read) -> io::Result<u64> {
+    let mut ret = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        r.read_exact(&mut byte)?;
+        ret |= ((byte[0] & 0x7F) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(ret);
+        }
+    }
+    Err(invalid_data("varint is too long"))
+}
+
+fn write_string(w: &mut impl Write, s: &str) -> io::Result<()> {
+    write_varint(w, s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_string(r: &mut impl Read) -> io::Result<String> {
+    let len = read_varint(r)?;
+    if len > MAX_STRING_LEN {
+        return Err(invalid_data(format!("header string of {} bytes is too long", len)));
+    }
+    let mut buf = vec![0u8; len as usize];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn read_usize(r: &mut impl Read) -> io::Result<usize> {
+    usize::try_from(read_varint(r)?).map_err(|e| invalid_data(e.to_string()))
+}
+
+/// Writes a fingerprint and its header
+pub fn write_fingerprint(w: &mut impl Write, header: &FingerprintHeader, fingerprint: &FingerprintSeq) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&[FORMAT_VERSION, lang_tag(header.lang), header.unit as u8])?;
+    write_varint(w, header.params.k() as u64)?;
+    write_varint(w, header.params.t() as u64)?;
+    write_string(w, &header.hash_version)?;
+    write_string(w, &header.strategy)?;
+    w.write_all(&header.source_sha256)?;
+
+    write_varint(w, fingerprint.len() as u64)?;
+    let mut prev = 0i64;
+    for (pos, hash) in fingerprint {
+        let delta = *pos as i64 - prev;
+        write_varint(w, ((delta << 1) ^ (delta >> 63)) as u64)?; // zigzag
+        write_varint(w, *hash)?;
+        prev = *pos as i64;
+    }
+
+    Ok(())
+}
+
+/// Reads a fingerprint and its header.  Fails with `io::ErrorKind::InvalidData` if the data is not a fingerprint, or was written by a newer
+/// version of the format.  Older versions are still read
+pub fn read_fingerprint(r: &mut impl Read) -> io::Result<(FingerprintHeader, FingerprintSeq)> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a fingerprint file"));
+    }
+
+    let mut version_lang = [0u8; 2];
+    r.read_exact(&mut version_lang)?;
+    if version_lang[0] == 0 || version_lang[0] > FORMAT_VERSION {
+        return Err(invalid_data(format!("unsupported fingerprint format version {}", version_lang[0])));
+    }
+    let lang = lang_from_tag(version_lang[1]).ok_or_else(|| invalid_data(format!("unknown language tag {}", version_lang[1])))?;
+    let unit = if version_lang[0] == 1 {
+        KGramUnit::Chars
+    }
+    else {
+        let mut tag = [0u8];
+        r.read_exact(&mut tag)?;
+        [KGramUnit::Chars, KGramUnit::Tokens].get(tag[0] as usize).copied().ok_or_else(|| invalid_data(format!("unknown k-gram unit tag {}", tag[0])))?
+    };
+
+    let (k, t) = (read_usize(r)?, read_usize(r)?);
+    let params = FingerprintParams::new(k, t).map_err(|e| invalid_data(e.to_string()))?;
+    let hash_version = read_string(r)?;
+    let strategy = read_string(r)?;
+    let mut source_sha256 = [0u8; 32];
+    r.read_exact(&mut source_sha256)?;
+
+    let count = read_usize(r)?;
+    let mut fingerprint = Vec::with_capacity(count.min(1 << 16)); // the count is not trusted for the allocation
+    let mut prev = 0i64;
+    for _ in 0..count {
+        let zigzag = read_varint(r)?;
+        let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+        let pos = prev.checked_add(delta).ok_or_else(|| invalid_data("fingerprint position is out of range"))?;
+        let pos_usize = usize::try_from(pos).map_err(|_| invalid_data("negative fingerprint position"))?;
+        fingerprint.push((pos_usize, read_varint(r)?));
+        prev = pos;
+    }
+
+    Ok((FingerprintHeader { lang, unit, params, hash_version, strategy, source_sha256 }, fingerprint))
+}
@@ -1,75 +1,145 @@
 //! The fingerprint module is responsible for the computation and storage of document fingerprints.  Full document fingerprints are computed by first preprocessing a file, and then
 //! computing the fingerprints of the preprocessed file.
 
-use std::collections::VecDeque;
+use crate::hash::{HashParams, self};
+use crate::preprocessor::{tokenize, Seq};
 
-use crate::hash::{RollingHash, self};
-use crate::preprocessor::Seq;
+mod strategy;
+pub mod format;
+pub use strategy::{FingerprintStrategy, Winnowing, RobustWinnowing, ModP, AllKGrams};
 
 // TODO: maybe implement a more robust process rather than just as libraries
-// TODO: tests!!!
 
 /// Defined in the same way as crate::preprocessor::Seq, but stores the fingerprint of a document instead of its preprocessed form.
 /// The first element of each tuple is the position that the fingerprint starts at in the original document, and the second element is 
 /// the fingerprint.
 pub type FingerprintSeq = Vec<(usize, u64)>;
 
+/// Errors from invalid fingerprinting parameters
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum FingerprintError {
+    /// `k` must be at least 1
+    ZeroNoiseThreshold,
+    /// `t` must be at least `k`
+    GuaranteeBelowNoise { k: usize, t: usize },
+    /// The modulus of `ModP` must be at least 1
+    ZeroModulus,
+}
+
+impl std::fmt::Display for FingerprintError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroNoiseThreshold => write!(f, "noise threshold k must be at least 1"),
+            Self::GuaranteeBelowNoise { k, t } => write!(f, "guarantee threshold t = {} must be at least noise threshold k = {}", t, k),
+            Self::ZeroModulus => write!(f, "modulus p must be at least 1"),
+        }
+    }
+}
+
+impl std::error::Error for FingerprintError {}
+
+/// Validated fingerprinting parameters, which satisfy `1 <= k <= t`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FingerprintParams {
+    k: usize,
+    t: usize,
+}
+
+impl FingerprintParams {
+    /// See `get_fingerprint` for what `k` and `t` mean
+    pub fn new(k: usize, t: usize) -> Result<Self, FingerprintError> {
+        if k == 0 {
+            Err(FingerprintError::ZeroNoiseThreshold)
+        }
+        else if t < k {
+            Err(FingerprintError::GuaranteeBelowNoise { k, t })
+        }
+        else {
+            Ok(Self { k, t })
+        }
+    }
+
+    /// The noise threshold
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// The guarantee threshold
+    pub fn t(&self) -> usize {
+        self.t
+    }
+
+    /// The number of k-grams in each winnowing window
+    pub fn window(&self) -> usize {
+        self.t - self.k + 1
+    }
+}
+
+/// The unit that `k` and `t` are measured in.  Fingerprints made with different units are not comparable
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum KGramUnit {
+    /// Characters of the preprocessed document, as in `get_fingerprint`
+    #[default]
+    Chars,
+    /// Tokens of the preprocessed document (see `preprocessor::tokenize`), as in `get_token_fingerprint`
+    Tokens,
+}
+
+impl KGramUnit {
+    /// Identifies the unit, so that it can be recorded alongside the fingerprints made with it
+    pub fn name(self) -> &'static str {
+        match self {
+            KGramUnit::Chars => "chars",
+            KGramUnit::Tokens => "tokens",
+        }
+    }
+}
+
 /// Fingerprinting: converts a preprocessed sequence of characters into a sequence of fingerprints.  Each fingerprint is annotated with the position that it
-/// begins at in the original document.  The algorithm is known as "Robust Winnowing", and is described in the paper "Winnowing: Local Algorithms for Document Fingerprinting" 
+/// begins at in the original document.  The algorithm is known as "Robust Winnowing", and is described in the paper "Winnowing: Local Algorithms for Document Fingerprinting".
+/// Other selection algorithms can be used with `get_fingerprint_with_params` (see `FingerprintStrategy`)
 /// 
 /// The fingerprint is guaranteed to find matches of at least `guarantee_threshold` characters and drop all matches of at most `noise_threshold` characters.
 /// 
+/// Fails if the parameters are invalid (see `FingerprintParams`).  Documents shorter than `t` characters (i.e. empty files) have an empty
+/// fingerprint, and a warning is logged.
+/// 
 /// # Arguments
 /// * `in_seq` - The preprocessed sequence of characters to fingerprint
 /// * `k` - The 'noise threshold': all matches under this size will be ignored
 /// * `t` - The 'guarantee threshold': all matches at least this size will be guaranteed to be found when comparing fingerprints
-pub fn get_fingerprint(in_seq: &Seq, k: usize, t: usize) -> FingerprintSeq {
-    // First compute all k-grams
-    let hash_seq = {
-        let mut initial_state = RollingHash::from_iter(in_seq[0..k].iter().map(|(_, c)| *c));
-        let mut ret = vec![(0 as usize, initial_state.get_u64())];
-        for ((i, win_start), (_, win_end)) in in_seq.iter().zip(in_seq[k..].iter()) {
-            initial_state.push_char(*win_end);
-            initial_state.pop_char(*win_start);
-            ret.push((*i, initial_state.get_u64()));
-        }
-        
-        ret
-    };
+pub fn get_fingerprint(in_seq: &Seq, k: usize, t: usize) -> Result<FingerprintSeq, FingerprintError> {
+    Ok(get_fingerprint_with_params(in_seq, &FingerprintParams::new(k, t)?, HashParams::DEFAULT, &RobustWinnowing))
+}
+
+/// Same as `get_fingerprint`, but with already validated parameters, and with the given hash parameters and selection strategy instead of
+/// the defaults.  Every strategy gives an empty fingerprint for documents shorter than `t`, so that results are comparable across strategies
+pub fn get_fingerprint_with_params(in_seq: &Seq, params: &FingerprintParams, hash_params: HashParams, strategy: &dyn FingerprintStrategy) -> FingerprintSeq {
+    if in_seq.len() < params.t() {
+        log::warn!("Document of length {} is shorter than the guarantee threshold t = {}, so its fingerprint is empty", in_seq.len(), params.t());
+        return vec![];
+    }
+
+    // First compute all k-grams, each annotated with the position of its first symbol
+    let symbols = in_seq.iter().map(|(_, s)| u64::from(*s)).collect::<Vec<_>>();
+    let hash_seq = in_seq.iter().map(|(i, _)| *i).zip(hash::kgram_hashes_with_params(&symbols, params.k(), hash_params)).collect::<Vec<_>>();
 
     // We will now compute the fingerprint
-    let w = t - k + 1; // window size
-    let mut smallest_hashes: VecDeque<(usize, u64)> = VecDeque::new(); // Increasing mono-deq
-    let mut fingerprint = vec![];
-
-    for (i, h_pair) in hash_seq.iter().enumerate() {
-        // Add new hash to smallest_hashes
-        while smallest_hashes.back().is_some_and(|(_, h)| *h >= h_pair.1) {
-            smallest_hashes.pop_back();
-        }
-        smallest_hashes.push_back(*h_pair);
+    let hashes = hash_seq.iter().map(|(_, h)| *h).collect::<Vec<_>>();
+    strategy.select(&hashes, params.window()).into_iter().map(|i| hash_seq[i]).collect()
+}
 
-        // Pop old hashes from smallest_hashes
-        while smallest_hashes.front().is_some_and(|(j, _)| *j <= i - w) {
-            smallest_hashes.pop_front();
-        }
+/// Same as `get_fingerprint`, but `k` and `t` are measured in tokens (see `preprocessor::tokenize`) rather than characters, so that the same
+/// parameters behave the same way for every language.  Positions still refer to the original document
+pub fn get_token_fingerprint(in_seq: &Seq, k: usize, t: usize) -> Result<FingerprintSeq, FingerprintError> {
+    get_fingerprint(&tokenize(in_seq), k, t)
+}
 
-        // Update fingerprint
-        if i >= w - 1 {
-            let window_hash = {
-                let best_dq_hash = smallest_hashes.front().unwrap(); 
-                match fingerprint.last() {
-                    Some(back@(back_i, back_h))
-                        if *back_i > i - w && *back_h <= best_dq_hash.1 => Some(back), 
-                    _ => None
-                }
-            };
-
-            if let Some(h) = window_hash {
-                fingerprint.push(*h);
-            }
-        }
-    }
+/// Same as `get_token_fingerprint`, but with already validated parameters, and with the given hash parameters and selection strategy instead
+/// of the defaults (see `get_fingerprint_with_params`)
+pub fn get_token_fingerprint_with_params(in_seq: &Seq, params: &FingerprintParams, hash_params: HashParams, strategy: &dyn FingerprintStrategy) -> FingerprintSeq {
+    get_fingerprint_with_params(&tokenize(in_seq), params, hash_params, strategy)
+}
 
-    fingerprint
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests;
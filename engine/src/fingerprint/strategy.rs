@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+
+use super::FingerprintError;
+
+/// A way of selecting which k-gram hashes of a document make up its fingerprint.  Selecting fewer hashes makes fingerprints smaller, at the
+/// cost of missing some matches.  See `notes/algorithm.md` for the trade-offs
+pub trait FingerprintStrategy {
+    /// Selects hashes from the hashes of every k-gram of a document, returning their indices in increasing order.  `window` is the number of
+    /// k-grams in a window, for strategies that use one
+    fn select(&self, hashes: &[u64], window: usize) -> Vec<usize>;
+
+    /// Identifies the strategy and its settings, so that it can be recorded alongside the fingerprints it produced.  Fingerprints from
+    /// different strategies should not be compared
+    fn name(&self) -> String;
+}
+
+/// Plain winnowing: the rightmost minimum hash of every window.  Guarantees that matches of at least `t` characters are found
+pub struct Winnowing;
+
+/// Robust winnowing: like `Winnowing`, but ties prefer the hash selected by the previous window.  This selects far fewer hashes in
+/// low-entropy documents (i.e. long runs of the same character), with the same guarantee
+pub struct RobustWinnowing;
+
+/// "0 mod p" sampling: every hash that is divisible by `p`, so about 1 in `p` hashes.  Matches of any length can be missed
+pub struct ModP {
+    p: u64,
+}
+
+impl ModP {
+    pub fn new(p: u64) -> Result<Self, FingerprintError> {
+        if p == 0 { Err(FingerprintError::ZeroModulus) } else { Ok(Self { p }) }
+    }
+}
+
+/// Every k-gram hash, which finds every match of at least `k` characters but is as large as the document
+pub struct AllKGrams;
+
+/// Selects the minimum hash of every window of `w` consecutive hashes.  Ties go to the rightmost minimum, or if `robust` is set, to the hash
+/// selected by the previous window if it is still in the window.  Returns the indices of the selected hashes in increasing order, with each
+/// index appearing once even if it is selected by several windows.  Returns nothing if there are fewer than `w` hashes
+fn winnow(hashes: &[u64], w: usize, robust: bool) -> Vec<usize> {
+    let mut smallest_hashes: VecDeque<usize> = VecDeque::new(); // Indices of an increasing mono-deq, the front is the rightmost minimum
+    let mut selected = vec![];
+
+    for (i, h) in hashes.iter().enumerate() {
+        // Add new hash to smallest_hashes.  Equal hashes are popped so that the rightmost one is kept
+        while smallest_hashes.back().is_some_and(|j| hashes[*j] >= *h) {
+            smallest_hashes.pop_back();
+        }
+        smallest_hashes.push_back(i);
+
+        // Pop old hashes from smallest_hashes
+        while smallest_hashes.front().is_some_and(|j| *j + w <= i) {
+            smallest_hashes.pop_front();
+        }
+
+        // Update fingerprint
+        if i + 1 >= w {
+            let best = *smallest_hashes.front().unwrap();
+            let keep_previous = robust && selected.last().is_some_and(|prev: &usize| *prev + w > i && hashes[*prev] == hashes[best]);
+            if !keep_previous && selected.last() != Some(&best) {
+                selected.push(best);
+            }
+        }
+    }
+
+    selected
+}
+
+impl FingerprintStrategy for Winnowing {
+    fn select(&self, hashes: &[u64], window: usize) -> Vec<usize> {
+        winnow(hashes, window, false)
+    }
+
+    fn name(&self) -> String {
+        String::from("winnowing")
+    }
+}
+
+impl FingerprintStrategy for RobustWinnowing {
+    fn select(&self, hashes: &[u64], window: usize) -> Vec<usize> {
+        winnow(hashes, window, true)
+    }
+
+    fn name(&self) -> String {
+        String::from("robust-winnowing")
+    }
+}
+
+impl FingerprintStrategy for ModP {
+    fn select(&self, hashes: &[u64], _window: usize) -> Vec<usize> {
+        (0..hashes.len()).filter(|i| hashes[*i] % self.p == 0).collect()
+    }
+
+    fn name(&self) -> String {
+        format!("mod-p-{}", self.p)
+    }
+}
+
+impl FingerprintStrategy for AllKGrams {
+    fn select(&self, hashes: &[u64], _window: usize) -> Vec<usize> {
+        (0..hashes.len()).collect()
+    }
+
+    fn name(&self) -> String {
+        String::from("all-kgrams")
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+
+use super::*;
+
+/// Simple LCG, so that tests are deterministic without extra dependencies
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+}
+
+/// Winnowing and robust winnowing, straight from the definitions in notes/algorithm.md: O(n * w)
+fn naive_winnow(hashes: &[u64], w: usize, robust: bool) -> Vec<usize> {
+    let mut selected: Vec<usize> = vec![];
+    for end in w..=hashes.len() {
+        let window = end - w..end;
+        let min = *hashes[window.clone()].iter().min().unwrap();
+        let prev = selected.last().copied().filter(|p| robust && window.contains(p) && hashes[*p] == min);
+        let pick = prev.unwrap_or_else(|| window.clone().rev().find(|i| hashes[*i] == min).unwrap());
+        if selected.last() != Some(&pick) {
+            selected.push(pick);
+        }
+    }
+    selected
+}
+
+fn random_seq(rng: &mut Lcg, len: usize, alphabet: &[char]) -> Seq {
+    (0..len).map(|i| (i, alphabet[rng.next() as usize % alphabet.len()].into())).collect()
+}
+
+fn hashes_of(fp: &FingerprintSeq) -> HashSet<u64> {
+    fp.iter().map(|(_, h)| *h).collect()
+}
+
+#[test]
+fn winnow_matches_reference() {
+    let mut rng = Lcg(1);
+    for _ in 0..200 {
+        let len = rng.next() as usize % 200;
+        let range = [2, 3, 1000, u64::MAX][rng.next() as usize % 4]; // low-entropy inputs have lots of ties
+        let hashes = (0..len).map(|_| rng.next() % range).collect::<Vec<_>>();
+        for w in 1..=8 {
+            assert_eq!(RobustWinnowing.select(&hashes, w), naive_winnow(&hashes, w, true), "hashes {:?}, w {}", hashes, w);
+            assert_eq!(Winnowing.select(&hashes, w), naive_winnow(&hashes, w, false), "hashes {:?}, w {}", hashes, w);
+        }
+    }
+}
+
+#[test]
+fn winnow_low_entropy() {
+    // A run of equal hashes is only selected once per window length, instead of once per window
+    assert_eq!(RobustWinnowing.select(&[5; 20], 4), vec![3, 7, 11, 15, 19]);
+    assert_eq!(RobustWinnowing.select(&[3, 1, 2, 1, 4, 1], 3), vec![1, 3]); // the last window keeps 3 instead of moving to the rightmost 5
+    assert_eq!(RobustWinnowing.select(&[3, 1, 2], 4), Vec::<usize>::new());
+    assert_eq!(Winnowing.select(&[5; 20], 4), (3..20).collect::<Vec<_>>());
+}
+
+#[test]
+fn fingerprint_positions() {
+    let mut rng = Lcg(2);
+    let seq = random_seq(&mut rng, 300, &['a', 'b', 'c', 'd']).into_iter().map(|(i, c)| (i * 2 + 7, c)).collect::<Seq>();
+    let fp = get_fingerprint(&seq, 5, 12).unwrap();
+
+    assert!(!fp.is_empty());
+    assert!(fp.windows(2).all(|p| p[0].0 < p[1].0));
+    for (pos, h) in &fp {
+        let start = seq.iter().position(|(i, _)| i == pos).unwrap();
+        assert_eq!(*h, crate::hash::RollingHash::<u64>::from_iter(seq[start..start + 5].iter().map(|(_, c)| u64::from(*c))).get_u64());
+    }
+}
+
+/// A symbol for a char hashes the same way as the char itself
+#[test]
+fn symbols_hash_as_chars() {
+    let text = "int main() { return 0; }";
+    let seq = crate::preprocessor::apply(text, &vec![]);
+    let chars = text.chars().collect::<Vec<_>>();
+    let hashes = crate::hash::kgram_hashes(&chars, 5);
+    assert_eq!(get_fingerprint(&seq, 5, 5).unwrap(), hashes.into_iter().enumerate().collect::<FingerprintSeq>());
+}
+
+/// Shared substrings of at least `t` chars always share a fingerprint, and shared substrings shorter than `k` never do
+#[test]
+fn fingerprint_thresholds() {
+    let (k, t) = (5, 12);
+    let mut rng = Lcg(3);
+    for _ in 0..50 {
+        // Different alphabets, so the only possible matches are the inserted ones
+        let (a, b) = (random_seq(&mut rng, 150, &['a', 'b', 'c']), random_seq(&mut rng, 150, &['x', 'y', 'z']));
+        let join = |mut base: Seq, at: usize, shared: &Seq| { base.splice(at..at, shared.iter().copied()); base.iter().enumerate().map(|(i, (_, c))| (i, *c)).collect::<Seq>() };
+
+        let shared = random_seq(&mut rng, t, &['a', 'b', 'c', 'x', 'y', 'z']);
+        let (fa, fb) = (get_fingerprint(&join(a.clone(), 40, &shared), k, t).unwrap(), get_fingerprint(&join(b.clone(), 90, &shared), k, t).unwrap());
+        assert!(!hashes_of(&fa).is_disjoint(&hashes_of(&fb)));
+
+        let noise = random_seq(&mut rng, k - 1, &['a', 'b', 'c', 'x', 'y', 'z']);
+        let (fa, fb) = (get_fingerprint(&join(a, 40, &noise), k, t).unwrap(), get_fingerprint(&join(b, 90, &noise), k, t).unwrap());
+        assert!(hashes_of(&fa).is_disjoint(&hashes_of(&fb)));
+    }
+}
+
+#[test]
+fn params_validated() {
+    assert_eq!(FingerprintParams::new(0, 5), Err(FingerprintError::ZeroNoiseThreshold));
+    assert_eq!(FingerprintParams::new(6, 5), Err(FingerprintError::GuaranteeBelowNoise { k: 6, t: 5 }));
+    assert_eq!(FingerprintParams::new(5, 5).unwrap().window(), 1);
+    assert!(get_fingerprint(&vec![(0, 'a'.into()); 20], 6, 5).is_err());
+    assert!(get_token_fingerprint(&vec![(0, 'a'.into()); 20], 0, 5).is_err());
+}
+
+#[test]
+fn short_inputs() {
+    let seq = "abcdefgh".chars().enumerate().map(|(i, c)| (i, c.into())).collect::<Seq>();
+    assert_eq!(get_fingerprint(&vec![], 3, 5), Ok(vec![]));
+    assert_eq!(get_fingerprint(&seq[..2].to_vec(), 3, 5), Ok(vec![]));
+    assert_eq!(get_fingerprint(&seq[..4].to_vec(), 3, 5), Ok(vec![])); // a k-gram, but not a full window
+    assert_eq!(get_fingerprint(&seq[..5].to_vec(), 3, 5).unwrap().len(), 1);
+}
+
+#[test]
+fn sampling_strategies() {
+    let hashes = [6, 7, 9, 12, 13, 3];
+    assert_eq!(ModP::new(3).unwrap().select(&hashes, 2), vec![0, 2, 3, 5]);
+    assert_eq!(AllKGrams.select(&hashes, 2), vec![0, 1, 2, 3, 4, 5]);
+    assert!(ModP::new(0).is_err());
+
+    let strategies: Vec<Box<dyn FingerprintStrategy>> = vec![Box::new(Winnowing), Box::new(RobustWinnowing), Box::new(ModP::new(4).unwrap()), Box::new(AllKGrams)];
+    let names = strategies.iter().map(|s| s.name()).collect::<HashSet<_>>();
+    assert_eq!(names.len(), strategies.len());
+    assert_ne!(ModP::new(4).unwrap().name(), ModP::new(5).unwrap().name());
+
+    // Every strategy selects a subset of all k-grams, and the shortest-input rule applies to all of them
+    let seq = random_seq(&mut Lcg(4), 200, &['a', 'b', 'c']);
+    let params = FingerprintParams::new(4, 10).unwrap();
+    let all = hashes_of(&get_fingerprint_with_params(&seq, &params, HashParams::DEFAULT, &AllKGrams));
+    for strategy in &strategies {
+        assert!(hashes_of(&get_fingerprint_with_params(&seq, &params, HashParams::DEFAULT, strategy.as_ref())).is_subset(&all));
+        assert!(get_fingerprint_with_params(&seq[..9].to_vec(), &params, HashParams::DEFAULT, strategy.as_ref()).is_empty());
+    }
+}
+
+#[test]
+fn binary_format_roundtrip() {
+    use crate::preprocessor::langs::Lang;
+    use format::{FingerprintHeader, read_fingerprint, write_fingerprint};
+
+    let source = "int main() { return 0; }";
+    let params = FingerprintParams::new(5, 12).unwrap();
+    let header = FingerprintHeader::new(Lang::Cpp, KGramUnit::Tokens, params, HashParams::from_seed(7), &RobustWinnowing, source);
+    // Positions can go backwards after reordering
+    let fp: FingerprintSeq = vec![(100, 1 << 60), (3, 0), (3, 12345), (400000, u64::MAX), (0, 1)];
+
+    let mut buf = vec![];
+    write_fingerprint(&mut buf, &header, &fp).unwrap();
+    let (read_header, read_fp) = read_fingerprint(&mut buf.as_slice()).unwrap();
+    assert_eq!(read_header, header);
+    assert_eq!(read_fp, fp);
+    assert_eq!(read_header.source_sha256[..4], [0x80, 0xa7, 0x16, 0x10]);
+
+    // Small deltas and hashes take few bytes
+    let mut small = vec![];
+    write_fingerprint(&mut small, &header, &(0..1000).map(|i| (i * 3, i as u64)).collect()).unwrap();
+    assert!(small.len() < 100 + 1000 * 3);
+
+    let empty_header = FingerprintHeader::new(Lang::Text, KGramUnit::Chars, params, HashParams::DEFAULT, &AllKGrams, "");
+    let mut empty = vec![];
+    write_fingerprint(&mut empty, &empty_header, &vec![]).unwrap();
+    assert_eq!(read_fingerprint(&mut empty.as_slice()).unwrap(), (empty_header, vec![]));
+}
+
+/// Fingerprint files can be written and read by users of the crate, so everything in the header must be reachable from public paths
+#[test]
+fn binary_format_public_paths() {
+    use crate::Lang;
+    use crate::fingerprint::{KGramUnit, FingerprintParams, ModP};
+    use crate::fingerprint::format::{FingerprintHeader, read_fingerprint, write_fingerprint};
+    use crate::hash::HashParams;
+
+    let header = FingerprintHeader::new(Lang::Python, KGramUnit::Tokens, FingerprintParams::new(4, 8).unwrap(), HashParams::from_seed(3), &ModP::new(4).unwrap(), "print(1)");
+    let mut buf = vec![];
+    write_fingerprint(&mut buf, &header, &vec![(0, 7)]).unwrap();
+    assert_eq!(read_fingerprint(&mut buf.as_slice()).unwrap(), (header, vec![(0, 7)]));
+}
+
+#[test]
+fn binary_format_invalid() {
+    use crate::preprocessor::langs::Lang;
+    use format::{FingerprintHeader, read_fingerprint, write_fingerprint};
+    use std::io::ErrorKind;
+
+    let header = FingerprintHeader::new(Lang::Java, KGramUnit::Chars, FingerprintParams::new(3, 4).unwrap(), HashParams::DEFAULT, &Winnowing, "class A {}");
+    let mut buf = vec![];
+    write_fingerprint(&mut buf, &header, &vec![(1, 2), (3, 4)]).unwrap();
+
+    assert_eq!(read_fingerprint(&mut &b"PK\x03\x04 not a fingerprint"[..]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(read_fingerprint(&mut &buf[..buf.len() - 1]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+    let mut newer = buf.clone();
+    newer[4] = 3;
+    assert_eq!(read_fingerprint(&mut newer.as_slice()).unwrap_err().kind(), ErrorKind::InvalidData);
+    let mut bad_lang = buf.clone();
+    bad_lang[5] = 200;
+    assert_eq!(read_fingerprint(&mut bad_lang.as_slice()).unwrap_err().kind(), ErrorKind::InvalidData);
+    let mut bad_unit = buf.clone();
+    bad_unit[6] = 2;
+    assert_eq!(read_fingerprint(&mut bad_unit.as_slice()).unwrap_err().kind(), ErrorKind::InvalidData);
+
+    // Version 1 had no k-gram unit, since it only had char fingerprints
+    let mut v1 = buf.clone();
+    v1[4] = 1;
+    v1.remove(6);
+    assert_eq!(read_fingerprint(&mut v1.as_slice()).unwrap(), (header.clone(), vec![(1, 2), (3, 4)]));
+
+    // Two fingerprints, at i64::MAX and then one past it
+    let mut overflow = vec![];
+    write_fingerprint(&mut overflow, &header, &vec![]).unwrap();
+    overflow.pop();
+    overflow.push(2);
+    overflow.extend([0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0]);
+    overflow.extend([2, 0]);
+    assert_eq!(read_fingerprint(&mut overflow.as_slice()).unwrap_err().kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,248 @@
+extern crate flate2;
+extern crate tar;
+extern crate zip;
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use super::FS;
+
+/// Limits applied while reading an archive, to protect against zip bombs and similar malicious uploads.
+/// Reading fails as soon as any of the limits is exceeded.
+pub struct ArchiveLimits {
+    /// Maximum number of files in the archive
+    pub max_entries: usize,
+    /// Maximum uncompressed size of a single file, in bytes
+    pub max_file_size: u64,
+    /// Maximum uncompressed size of all files combined, in bytes
+    pub max_total_size: u64,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            max_file_size: 16 << 20,
+            max_total_size: 256 << 20,
+        }
+    }
+}
+
+/// A read-only filesystem handle over the contents of a `.zip`, `.tar`, `.tar.gz` or `.tgz` archive.
+///
+/// The archive is read into memory once on creation.  Paths inside the archive are preserved relative to the archive root, and entries that
+/// would escape the root (absolute paths or `..` components) are rejected, as are archives with duplicate entries.  Since every archive gets its own handle and identifier, an
+/// archive can be treated as a single submission.
+pub struct ArchiveFSHandle {
+    archive: PathBuf,
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+/// Tracks the limits while an archive is being read
+struct LimitTracker<'a> {
+    limits: &'a ArchiveLimits,
+    entries: usize,
+    total_size: u64,
+}
+
+impl LimitTracker<'_> {
+    /// Reads a single entry, failing if it would exceed the limits.  `declared_size` is the size claimed by the archive, which is checked
+    /// up front but not trusted
+    fn read_entry(&mut self, reader: impl Read, declared_size: u64) -> Result<Vec<u8>, io::Error> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(limit_error(format!("archive has more than {} entries", self.limits.max_entries)));
+        }
+
+        let max_size = self.limits.max_file_size.min(self.limits.max_total_size - self.total_size);
+        if declared_size > max_size {
+            return Err(limit_error(format!("archive entry of {} bytes exceeds size limits", declared_size)));
+        }
+
+        let mut ret = vec![];
+        reader.take(max_size + 1).read_to_end(&mut ret)?;
+        if ret.len() as u64 > max_size {
+            return Err(limit_error(String::from("archive entry exceeds size limits")));
+        }
+
+        self.total_size += ret.len() as u64;
+        Ok(ret)
+    }
+}
+
+fn limit_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_only_error() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "Archive filesystems are read-only")
+}
+
+/// Adds a file read from an archive.  An archive can hold several entries with the same path (i.e. a tar that was appended to), and since
+/// it is unclear which one is the submitted file, such archives are rejected
+fn insert_entry(files: &mut BTreeMap<PathBuf, Vec<u8>>, path: PathBuf, contents: Vec<u8>) -> Result<(), io::Error> {
+    if files.contains_key(&path) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Archive has more than one entry for {}", path.display())));
+    }
+    files.insert(path, contents);
+    Ok(())
+}
+
+/// Checks that a path stays inside the archive root
+fn sanitize_path(path: &Path) -> Result<PathBuf, io::Error> {
+    let mut ret = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(p) => ret.push(p),
+            Component::CurDir => {},
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Archive entry {} escapes the archive root", path.display()))),
+        }
+    }
+    Ok(ret)
+}
+
+impl ArchiveFSHandle {
+    /// Opens an archive with the given limits.  The archive type is determined by the file extension
+    pub fn new(archive: &Path, limits: &ArchiveLimits) -> Result<Self, io::Error> {
+        Self::from_reader(archive, std::fs::File::open(archive)?, limits)
+    }
+
+    /// Same as `new`, but for an archive that was already read into memory (i.e. from another filesystem).  `archive` is only used for its
+    /// extension and as the identifier of the handle
+    pub fn from_bytes(archive: &Path, contents: &[u8], limits: &ArchiveLimits) -> Result<Self, io::Error> {
+        Self::from_reader(archive, io::Cursor::new(contents), limits)
+    }
+
+    fn from_reader(archive: &Path, file: impl Read + Seek, limits: &ArchiveLimits) -> Result<Self, io::Error> {
+        let name = archive.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_lowercase();
+        let mut tracker = LimitTracker { limits, entries: 0, total_size: 0 };
+
+        let files = if name.ends_with(".zip") {
+            Self::read_zip(file, &mut tracker)?
+        }
+        else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Self::read_tar(flate2::read::GzDecoder::new(file), &mut tracker)?
+        }
+        else if name.ends_with(".tar") {
+            Self::read_tar(file, &mut tracker)?
+        }
+        else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported archive type: {}", archive.display())));
+        };
+
+        Ok(Self {
+            archive: archive.to_path_buf(),
+            files,
+        })
+    }
+
+    /// Checks whether a path looks like an archive that can be opened with `ArchiveFSHandle::new` or `ArchiveFSHandle::from_bytes`
+    pub fn is_archive(path: &Path) -> bool {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_lowercase();
+        [".zip", ".tar", ".tar.gz", ".tgz"].iter().any(|ext| name.ends_with(ext))
+    }
+
+    fn read_zip(file: impl Read + Seek, tracker: &mut LimitTracker) -> Result<BTreeMap<PathBuf, Vec<u8>>, io::Error> {
+        let mut archive = zip::ZipArchive::new(file)?;
+        let mut ret = BTreeMap::new();
+
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i)?;
+            if entry.is_dir() {
+                continue;
+            }
+
+            let path = sanitize_path(Path::new(entry.name()))?;
+            let size = entry.size();
+            insert_entry(&mut ret, path, tracker.read_entry(entry, size)?)?;
+        }
+
+        Ok(ret)
+    }
+
+    fn read_tar(reader: impl Read, tracker: &mut LimitTracker) -> Result<BTreeMap<PathBuf, Vec<u8>>, io::Error> {
+        let mut archive = tar::Archive::new(reader);
+        let mut ret = BTreeMap::new();
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() { // Directories, links, etc.
+                continue;
+            }
+
+            let path = sanitize_path(&entry.path()?)?;
+            let size = entry.header().size()?;
+            insert_entry(&mut ret, path, tracker.read_entry(entry, size)?)?;
+        }
+
+        Ok(ret)
+    }
+
+    /// Returns the files and (implicit) directories directly under `path`, or everything under it if recursive
+    fn children(&self, path: &Path, recursive: bool) -> Vec<PathBuf> {
+        let mut ret: Vec<PathBuf> = vec![];
+        for file in self.files.keys().filter(|f| f.starts_with(path) && *f != path) {
+            let entry = if recursive {
+                file.clone()
+            }
+            else {
+                path.join(file.strip_prefix(path).unwrap().components().next().unwrap())
+            };
+
+            if ret.last() != Some(&entry) { // keys are sorted, so duplicate directories are adjacent
+                ret.push(entry);
+            }
+        }
+
+        ret
+    }
+}
+
+impl FS for ArchiveFSHandle {
+    fn write_file(&self, _path: &Path, _contents: &str) -> Result<(), io::Error> {
+        Err(read_only_error())
+    }
+
+    fn remove_file(&self, _path: &Path) -> Result<(), io::Error> {
+        Err(read_only_error())
+    }
+
+    fn read_file(&self, path: &Path) -> Result<String, io::Error> {
+        String::from_utf8(self.read_bytes(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn read_bytes(&self, path: &Path) -> Result<Vec<u8>, io::Error> {
+        self.files.get(path).cloned().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found in archive", path.display())))
+    }
+
+    fn list_dir(&self, path: &Path, recursive: bool) -> Result<Vec<PathBuf>, io::Error> {
+        if !path.as_os_str().is_empty() && !self.is_dir(path) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not a directory in archive", path.display())));
+        }
+        Ok(self.children(path, recursive))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.is_file(path) || self.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.files.keys().any(|f| f.starts_with(path) && f != path)
+    }
+
+    fn clean(&self) -> Result<(), io::Error> {
+        Ok(()) // Nothing to clean, the archive is never modified
+    }
+
+    fn clear(&self) -> Result<(), io::Error> {
+        Err(read_only_error())
+    }
+
+    fn get_identifier(&self) -> String {
+        format!("ArchiveFS: {:?}", self.archive)
+    }
+}
@@ -83,6 +83,10 @@ impl FS for LocalFSHandle {
         std::fs::read_to_string(self.root.join(path))
     }
 
+    fn read_bytes(&self, path: &Path) -> Result<Vec<u8>, io::Error> {
+        std::fs::read(self.root.join(path))
+    }
+
     fn list_dir(&self, p: &Path, recursive: bool) -> Result<Vec<PathBuf>, io::Error> {
         let ret = self.list_dir_helper(self.root.join(p).as_path(), recursive)?;
         Ok(ret.into_iter().map(|path| path.strip_prefix(self.root.as_path()).unwrap().to_path_buf()).collect())
@@ -1,6 +1,7 @@
 //! Defines an abstraction and various implementations for a simple filesystem that can store and retrieve files in various (possibly nested) directories.
 //! 
 //! An abstraction is provided so that source files (which must be stored to perform matching) can be stored in a variety of ways, such as locally or on AWS S3.
+//! Archives (`.zip`, `.tar.gz`) uploaded as submissions can also be read directly as a read-only filesystem.
 
 use std::path::PathBuf;
 use std::{path::Path, io};
@@ -18,6 +19,14 @@ pub trait FS {
     /// Reads the contents of a file.  Will error if the path does not point to an existing file.
     fn read_file(&self, path: &Path) -> Result<String, io::Error>;
 
+    /// Reads the raw contents of a file, which may not be UTF-8 (i.e. binaries).  Will error if the path does not point to an existing file.
+    ///
+    /// The default implementation goes through `read_file`, so it fails on contents that are not UTF-8.  Filesystems that can hold
+    /// binaries should override it
+    fn read_bytes(&self, path: &Path) -> Result<Vec<u8>, io::Error> {
+        self.read_file(path).map(String::into_bytes)
+    }
+
     /// Lists the contents of a directory.  If recursive is true, will list all files in the directory and all subdirectories.
     /// Otherwise, it will just list the files and directories in the current directory.
     fn list_dir(&self, path: &Path, recursive: bool) -> Result<Vec<PathBuf>, io::Error>;
@@ -46,8 +55,10 @@ pub trait FS {
 // Filesystem types
 mod local;
 mod aws;
+mod archive;
 
 pub use local::LocalFSHandle;
+pub use archive::{ArchiveFSHandle, ArchiveLimits};
 
 #[cfg(test)]
 mod tests;
\ No newline at end of file
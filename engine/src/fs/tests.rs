@@ -1,5 +1,8 @@
-use super::{*, fs::FSHandle};
-use std::path::Path;
+use super::*;
+use crate::fs;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
 
 const TEST_FS_ROOT: &str = "test_fs_local";
 
@@ -88,4 +91,100 @@ fn test_fs_local_clean() {
         vec!["test.txt"].sort());
     assert_eq!(handle.list_dir(Path::new("test4"), false).unwrap().sort(),
         vec!["nested"].sort());
-}
\ No newline at end of file
+}
+
+/// Returns a path in the temporary directory that is unique to this test, so that tests can run in parallel (and in parallel runs)
+fn temp_path(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!("{}_{}_{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed), name))
+}
+
+/// Writes a small zip archive with a nested directory to a temporary file
+fn make_test_zip(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let path = temp_path(name);
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+    for (file, contents) in files {
+        writer.start_file(*file, zip::write::FileOptions::default()).unwrap();
+        writer.write_all(contents.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+    path
+}
+
+#[test]
+fn test_fs_archive_zip() {
+    let path = make_test_zip("moss_test_archive.zip", &[("main.cpp", "int main() {}"), ("src/a.cpp", "a"), ("src/lib/b.cpp", "b")]);
+    let handle = fs::ArchiveFSHandle::new(&path, &fs::ArchiveLimits::default()).unwrap();
+
+    assert_eq!(handle.read_file(Path::new("src/lib/b.cpp")).unwrap(), "b");
+    assert_eq!(handle.read_bytes(Path::new("main.cpp")).unwrap(), b"int main() {}");
+    assert!(handle.read_file(Path::new("src/c.cpp")).is_err());
+    assert!(handle.is_dir(Path::new("src/lib")));
+    assert!(handle.is_file(Path::new("main.cpp")));
+    assert!(!handle.exists(Path::new("lib")));
+
+    assert_eq!(handle.list_dir(Path::new(""), false).unwrap(), vec![Path::new("main.cpp"), Path::new("src")]);
+    assert_eq!(handle.list_dir(Path::new("src"), false).unwrap(), vec![Path::new("src/a.cpp"), Path::new("src/lib")]);
+    assert_eq!(handle.list_dir(Path::new(""), true).unwrap(), vec![Path::new("main.cpp"), Path::new("src/a.cpp"), Path::new("src/lib/b.cpp")]);
+
+    assert!(handle.write_file(Path::new("x.cpp"), "x").is_err());
+    assert!(handle.get_identifier().contains("moss_test_archive.zip"));
+
+    // Archives read from memory (i.e. from another filesystem) give the same files
+    let in_memory = fs::ArchiveFSHandle::from_bytes(&path, &std::fs::read(&path).unwrap(), &fs::ArchiveLimits::default()).unwrap();
+    assert_eq!(in_memory.list_dir(Path::new(""), true).unwrap(), handle.list_dir(Path::new(""), true).unwrap());
+    assert_eq!(in_memory.read_file(Path::new("src/a.cpp")).unwrap(), "a");
+
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Writes a gzipped tar archive to a temporary file
+fn make_test_tar_gz(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let path = temp_path(name);
+    let encoder = flate2::write::GzEncoder::new(std::fs::File::create(&path).unwrap(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (file, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, file, contents.as_bytes()).unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap();
+    path
+}
+
+#[test]
+fn test_fs_archive_tar_gz() {
+    let path = make_test_tar_gz("moss_test_archive.tar.gz", &[("sub/main.py", "print(1)"), ("README", "hi")]);
+
+    let handle = fs::ArchiveFSHandle::new(&path, &fs::ArchiveLimits::default()).unwrap();
+    assert_eq!(handle.read_file(Path::new("sub/main.py")).unwrap(), "print(1)");
+    assert_eq!(handle.list_dir(Path::new(""), true).unwrap(), vec![Path::new("README"), Path::new("sub/main.py")]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_fs_archive_duplicates() {
+    let path = make_test_tar_gz("moss_test_archive_duplicates.tar.gz", &[("main.py", "print(1)"), ("./main.py", "print(2)")]);
+
+    let err = fs::ArchiveFSHandle::new(&path, &fs::ArchiveLimits::default()).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("main.py"));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_fs_archive_limits() {
+    let big = "a".repeat(1000);
+    let path = make_test_zip("moss_test_archive_limits.zip", &[("a.txt", big.as_str()), ("b.txt", big.as_str())]);
+
+    let limits = |max_entries, max_file_size, max_total_size| fs::ArchiveLimits { max_entries, max_file_size, max_total_size };
+    assert!(fs::ArchiveFSHandle::new(&path, &limits(2, 1000, 2000)).is_ok());
+    assert!(fs::ArchiveFSHandle::new(&path, &limits(1, 1000, 2000)).is_err());
+    assert!(fs::ArchiveFSHandle::new(&path, &limits(2, 999, 2000)).is_err());
+    assert!(fs::ArchiveFSHandle::new(&path, &limits(2, 1000, 1999)).is_err());
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -1,105 +1,284 @@
 //! Implementation of the Rabin-karp rolling hash
 //! 
-//! Hashes are represented as values in the unsigned 64-bit range, with an internal 128-bit representation.
-//! The hashing algorithm uses a fixed base and modulus for deterministic behaviour, and the probability of collision
-//! is ~2^(-64).
+//! Hashes are computed modulo the Mersenne prime 2^61 - 1, so that reducing a product only takes shifts and adds instead of a 128-bit
+//! division, and are represented as values in the unsigned 64-bit range.
+//! The hashing algorithm uses a fixed prime modulus and a base given by a `HashParams`.  The default parameters are fixed for deterministic
+//! behaviour, but a secret seed can be used instead so that collisions cannot be crafted by reading the source, or to run a second
+//! independent hash to double-check a match.  For a base chosen at random, the probability of two distinct strings of length n colliding is
+//! at most ~n * 2^(-61).
 
-use static_init::dynamic;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::str::FromStr;
 
-const MODULUS: u128 = 18446744073709551557; // python3 -c "import sympy; f = lambda n: n if sympy.isprime(n) else f(n-1); print(f(2**64-1))"
-const BASE: u128 = 11842660086381224053; // python3 -c "import random; random.seed(22443256); print(random.randint(1, 2**64-1))"
+use sha2::{Digest, Sha256};
 
+const MODULUS: u64 = (1 << 61) - 1;
+const BASE: u64 = 313445040312754298; // python3 -c "import random; random.seed(22443256); print(random.randint(1, 2**64-1) % (2**61-1))"
+
+/// Reduces a value below 2^62 modulo 2^61 - 1
+#[inline(always)]
+fn reduce(a: u64) -> u64 { let c = (a & MODULUS) + (a >> 61); if c >= MODULUS { c - MODULUS } else { c } }
 #[inline(always)]
-fn mod_add(a: u128, b: u128) -> u128 { let c = a + b; if c >= MODULUS { c - MODULUS } else { c } }
+fn mod_add(a: u64, b: u64) -> u64 { let c = a + b; if c >= MODULUS { c - MODULUS } else { c } }
 #[inline(always)]
-fn mod_sub(a: u128, b: u128) -> u128 { let c = a + MODULUS - b; if c >= MODULUS { c - MODULUS } else { c } }
+fn mod_sub(a: u64, b: u64) -> u64 { let c = a + MODULUS - b; if c >= MODULUS { c - MODULUS } else { c } }
 #[inline(always)]
-fn mod_mul(a: u128, b: u128) -> u128 { (a * b) % MODULUS }
+fn mod_mul(a: u64, b: u64) -> u64 {
+    // a * b < 2^122, and 2^61 = 1 (mod 2^61 - 1), so the low 61 bits and the rest can be added together
+    let c = a as u128 * b as u128;
+    reduce((c as u64 & MODULUS) + (c >> 61) as u64)
+}
 
-const STORE_BASE_POWERS_TO: usize = 1 << 20;
-#[dynamic(lazy)]
-static BASE_POWERS: Vec<u128> = {
-    let ret = (0..STORE_BASE_POWERS_TO).fold(vec![1], |mut acc, _| { acc.push(mod_mul(*acc.last().unwrap(), BASE)); acc });
-    debug_assert!(ret.len() == STORE_BASE_POWERS_TO + 1);
+fn mod_pow(mut b: u64, mut e: usize) -> u64 {
+    let mut ret = 1;
+    while e > 0 {
+        if e & 1 == 1 {
+            ret = mod_mul(ret, b);
+        }
+        b = mod_mul(b, b);
+        e >>= 1;
+    }
     ret
-};
+}
 
-/// Quickly return BASE^p % MODULUS using cached result
-/// 
-/// For p > STORE_BASE_POWERS_TO, this function will try and calculate it as quickly as possible using the saved results.
-/// For p <= STORE_BASE_POWERS_TO, results are automatically pulled from cache in O(1)
-fn get_pow(mut p: usize) -> u128 {
-    if p > STORE_BASE_POWERS_TO {
-        let mut ret = 1;
-        while p > STORE_BASE_POWERS_TO {
-            p -= STORE_BASE_POWERS_TO;
-            ret = mod_mul(ret, BASE_POWERS[STORE_BASE_POWERS_TO]);
-        }
-        mod_mul(ret, BASE_POWERS[p])
+/// Version of the hashing algorithm, which is part of `HashParams::version`.  This must be bumped whenever a change to the algorithm changes
+/// the hash values it produces
+const ALGORITHM_VERSION: u32 = 2;
+
+/// The parameters of the rolling hash.  Hashes computed with different parameters are unrelated, so they should never be compared
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HashParams {
+    base: u64,
+}
+
+impl HashParams {
+    /// The fixed default parameters
+    pub const DEFAULT: HashParams = HashParams { base: BASE };
+
+    /// Derives parameters from a seed.  The same seed always gives the same parameters
+    pub fn from_seed(seed: u64) -> Self {
+        // splitmix64, so that similar seeds give unrelated bases
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+
+        Self { base: 2 + z % (MODULUS - 3) } // in [2, MODULUS - 2], since 0, 1 and -1 are degenerate bases
     }
-    else { BASE_POWERS[p] }
+
+    /// Returns a string that identifies the parameters and the algorithm version, which can be stored alongside hashes to check that they
+    /// are only compared with hashes made the same way.  The parameters only appear as a truncated SHA-256, so that a seeded base can't be
+    /// recovered from a database or a fingerprint file
+    pub fn version(&self) -> String {
+        let digest = Sha256::new().chain_update(MODULUS.to_le_bytes()).chain_update(self.base.to_le_bytes()).finalize();
+        format!("rk{}-{}", ALGORITHM_VERSION, hex::encode(&digest[..8]))
+    }
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A type that can be hashed as a single token by `RollingHash`, i.e. chars, interned symbols or AST node kinds
+pub trait HashToken: Copy {
+    /// The value of the token in the hash, which must be in `[1, 2^61 - 1)`.  It is never 0, so that pushing a zero token still changes
+    /// the hash
+    fn hash_value(self) -> u64;
 }
 
-/// Maintains the current state of the Rabin-Karp algorithm on a string.  The state can be updated by adding characters to the end,
-/// popping characters from the start, or clearing the state.
+impl HashToken for char {
+    #[inline(always)]
+    fn hash_value(self) -> u64 { self as u64 + 1 }
+}
+
+/// Integer tokens are taken modulo 2^61 - 2 (so only `u64`/`usize` tokens can ever collide), then shifted by 1
+macro_rules! impl_hash_token {
+    ($($t:ty),*) => {
+        $(impl HashToken for $t {
+            #[inline(always)]
+            fn hash_value(self) -> u64 { self as u64 % (MODULUS - 1) + 1 }
+        })*
+    };
+}
+impl_hash_token!(u8, u16, u32, u64, usize);
+
+/// Maintains the current state of the Rabin-Karp algorithm on a fixed-size window of a sequence of tokens (chars by default).  The state can
+/// be updated by adding tokens to the end until the window is full, popping the first token of a full window, or clearing the state.
+///
+/// Since the window size is fixed, popping only ever needs `base^(window - 1)`, which is computed once on construction.
 /// 
-/// Note that we store the hash of individual characters as `c + 1` instead of `c` so that pushing the null-byte changes the hash
-pub struct RollingHash {
-    val: u128,
-    len: usize
+/// Each token is hashed as its `HashToken::hash_value`, which for chars is `c + 1` instead of `c` so that pushing the null-byte changes the hash
+pub struct RollingHash<T: HashToken = char> {
+    val: u64,
+    len: usize,
+    window: usize,
+    /// base^(window - 1) % MODULUS, the weight of the first token of a full window
+    top: u64,
+    params: HashParams,
+    _token: PhantomData<T>,
 }
 
-impl RollingHash {
-    /// Constructs a new RollingHash state representing one with no characters
-    pub fn new() -> Self {
+impl<T: HashToken> RollingHash<T> {
+    /// Constructs a new RollingHash state representing one with no tokens, with the given window size and the default parameters
+    pub fn new(window: usize) -> Self {
+        Self::with_params(window, HashParams::DEFAULT)
+    }
+
+    /// Constructs a new RollingHash state representing one with no tokens, with the given window size
+    pub fn with_params(window: usize, params: HashParams) -> Self {
         Self {
             val: 0,
-            len: 0
+            len: 0,
+            window,
+            top: mod_pow(params.base, window.saturating_sub(1)),
+            params,
+            _token: PhantomData,
         }
     }
 
-    /// Constructs a new RollingHash state using an iterator as a string
-    pub fn from_iter(iter: impl Iterator<Item = char>) -> Self {
-        iter.fold(Self::new(), |mut acc, c| { acc.push_char(c); acc })
-    }
-
-    /// Constructs a new RollingHash state using a string
-    pub fn from_str(s: &str) -> Self {
-        RollingHash::from_iter(s.chars())
-    }
-
-    /// Updates the state as if a character was added to the end of the string
-    /// We use `c + 1` instead of `c` so that pushing the null-byte changes the hash
-    pub fn push_char(&mut self, c: char) {
-        self.val = mod_add(mod_mul(self.val, BASE), c as u128 + 1);
+    /// Updates the state as if a token was added to the end of the string.  The window must not be full
+    #[inline(always)]
+    pub fn push(&mut self, token: T) {
+        assert!(self.len < self.window, "Cannot push to a full window");
+        self.val = mod_add(mod_mul(self.val, self.params.base), token.hash_value());
         self.len += 1;
     }
 
-    /// Updates the state as if a character was removed from the start of the string
-    /// We use `c + 1` instead of `c` so that pushing the null-byte changes the hash
-    pub fn pop_char(&mut self, c: char) {
-        debug_assert!(self.len > 0, "Cannot pop from empty hash");
-        self.val = mod_sub(self.val, mod_mul(get_pow(self.len - 1), c as u128 + 1));
+    /// Updates the state as if a token was removed from the start of the string.  The window must be full, and `token` must be its first
+    /// token
+    #[inline(always)]
+    pub fn pop(&mut self, token: T) {
+        assert!(self.len == self.window, "Can only pop from a full window");
+        self.val = mod_sub(self.val, mod_mul(self.top, token.hash_value()));
         self.len -= 1;
     }
 
+    /// Slides a full window forward by one token: `out` (the first token) is popped and `token` is pushed
+    #[inline(always)]
+    pub fn roll(&mut self, out: T, token: T) {
+        self.pop(out);
+        self.push(token);
+    }
+
     /// Updates the state as if the string was cleared
     pub fn clear(&mut self) {
         self.val = 0;
         self.len = 0;
     }
 
-    /// Returns the number of characters in the string that the state represents
+    /// Returns the number of tokens in the string that the state represents
     pub fn len(&self) -> usize {
         self.len
     }
 
+    /// Returns whether the string that the state represents is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the window size
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
     /// Returns the hash value
     pub fn get_u64(&self) -> u64 {
-        self.val as u64
+        self.val
+    }
+
+    /// Returns the parameters used by the state
+    pub fn params(&self) -> HashParams {
+        self.params
+    }
+}
+
+impl<T: HashToken> FromIterator<T> for RollingHash<T> {
+    /// Constructs a new RollingHash state using an iterator as a string.  The window size is the length of the string
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let tokens = iter.into_iter().collect::<Vec<_>>();
+        tokens.iter().fold(Self::new(tokens.len()), |mut acc, t| { acc.push(*t); acc })
     }
 }
 
+impl FromStr for RollingHash<char> {
+    type Err = Infallible;
+
+    /// Constructs a new RollingHash state using a string.  The window size is the length of the string.  Never fails
+    fn from_str(s: &str) -> Result<Self, Infallible> {
+        Ok(s.chars().collect())
+    }
+}
+
+impl RollingHash<char> {
+    /// Same as `push`
+    pub fn push_char(&mut self, c: char) {
+        self.push(c);
+    }
+
+    /// Same as `pop`
+    pub fn pop_char(&mut self, c: char) {
+        self.pop(c);
+    }
+}
+
+/// Number of independent lanes used by `kgram_hashes`
+const LANES: usize = 4;
+
+/// Computes the hash of every window of `k` tokens in a sequence, using the default parameters.  See `kgram_hashes_with_params`
+pub fn kgram_hashes<T: HashToken>(tokens: &[T], k: usize) -> Vec<u64> {
+    kgram_hashes_with_params(tokens, k, HashParams::DEFAULT)
+}
+
+/// Computes the hash of every window of `k` tokens in a sequence.  The i-th hash is the hash of `tokens[i..i + k]`, and is the same as the
+/// one `RollingHash` gives for that window.  Returns no hashes if there are fewer than `k` tokens (or `k` is 0).
+///
+/// Rolling a single hash over the sequence is a chain of dependent multiplications, so the windows are split into `LANES` contiguous runs
+/// that are rolled in the same loop.  The runs are independent, so the CPU can work on all of them at once
+pub fn kgram_hashes_with_params<T: HashToken>(tokens: &[T], k: usize, params: HashParams) -> Vec<u64> {
+    if k == 0 || tokens.len() < k {
+        return vec![];
+    }
+
+    let (n, base, top) = (tokens.len() - k + 1, params.base, mod_pow(params.base, k - 1));
+    let window_hash = |start: usize| tokens[start..start + k].iter().fold(0, |acc, t| mod_add(mod_mul(acc, base), t.hash_value()));
+    // Hash of a window, given the hash of the previous window, the token that left it and the token that entered it
+    let step = |h: u64, out: T, token: T| mod_add(mod_mul(mod_sub(h, mod_mul(top, out.hash_value())), base), token.hash_value());
+
+    let mut ret = vec![0; n];
+    let lane_len = n / LANES;
+    let mut state: [u64; LANES] = std::array::from_fn(|l| window_hash(l * lane_len));
+    if lane_len > 0 {
+        // Slicing each lane up front lets the compiler drop the bounds checks in the loop
+        let outs: [&[T]; LANES] = std::array::from_fn(|l| &tokens[l * lane_len..l * lane_len + lane_len - 1]);
+        let ins: [&[T]; LANES] = std::array::from_fn(|l| &tokens[l * lane_len + k..l * lane_len + k + lane_len - 1]);
+        let mut chunks = ret.chunks_exact_mut(lane_len);
+        let lanes: [&mut [u64]; LANES] = std::array::from_fn(|_| chunks.next().unwrap());
+
+        for l in 0..LANES {
+            lanes[l][0] = state[l];
+        }
+        for j in 0..lane_len - 1 {
+            for l in 0..LANES {
+                state[l] = step(state[l], outs[l][j], ins[l][j]);
+                lanes[l][j + 1] = state[l];
+            }
+        }
+    }
+
+    // The last few windows (and all of them for short inputs) continue from the end of the last lane
+    let rest = LANES * lane_len;
+    let mut h = if rest == 0 { ret[0] = state[0]; state[0] } else { state[LANES - 1] };
+    for i in rest.max(1)..n {
+        h = step(h, tokens[i - 1], tokens[i + k - 1]);
+        ret[i] = h;
+    }
+
+    ret
+}
+
 #[cfg(test)]
 mod tests {
     use std::{vec, collections::HashMap};
@@ -114,13 +293,13 @@ mod tests {
     fn test_create_fns() {
         let test_str = String::from(TEST_STR_1);
 
-        let mut h1 = RollingHash::new();
+        let mut h1 = RollingHash::new(test_str.len());
         for c in test_str.chars() {
             h1.push_char(c);
         }
 
         let h2 = RollingHash::from_iter(test_str.chars());
-        let h3 = RollingHash::from_str(&test_str);
+        let h3 = test_str.parse::<RollingHash>().unwrap();
 
         assert_eq!(h1.get_u64(), h2.get_u64());
         assert_eq!(h1.get_u64(), h3.get_u64());
@@ -169,7 +348,7 @@ mod tests {
         }
     }
 
-    /// Checks if `push_char` and `pop_char` match `from_iter`/`from_str`
+    /// Checks if `push_char` and `pop_char` match `from_iter`
     #[test]
     fn test_push_pop() {
         let test_str: String = String::from(TEST_STR_2);
@@ -184,7 +363,7 @@ mod tests {
             // Generate hashes from push and pop
             let h_push_pop = {
                 let mut ret = vec![];
-                let mut h_state = RollingHash::new();
+                let mut h_state = RollingHash::new(w);
                 for c in &chars[..w-1] {
                     h_state.push_char(*c);
                 }
@@ -202,12 +381,138 @@ mod tests {
         }
     }
 
+    /// `roll` must match hashing each window from scratch
+    #[test]
+    fn test_roll() {
+        let chars = TEST_STR_2.chars().collect::<Vec<_>>();
+        let w = 7;
+        let mut h = RollingHash::new(w);
+        chars[..w].iter().for_each(|c| h.push_char(*c));
+        for i in w..chars.len() {
+            h.roll(chars[i - w], chars[i]);
+            assert_eq!(h.len(), w);
+            assert_eq!(h.get_u64(), RollingHash::from_iter(chars[i + 1 - w..=i].iter().copied()).get_u64());
+        }
+    }
+
+    /// A push past the window would silently give a hash of the wrong length, so it must panic in release builds too
+    #[test]
+    #[should_panic(expected = "Cannot push to a full window")]
+    fn test_overfill() {
+        let mut h = RollingHash::new(1);
+        h.push_char('a');
+        h.push_char('b');
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only pop from a full window")]
+    fn test_pop_partial() {
+        let mut h = RollingHash::new(2);
+        h.push_char('a');
+        h.pop_char('a');
+    }
+
+    /// Seeded parameters must be deterministic, independent of the default, and still roll correctly
+    #[test]
+    fn test_params() {
+        let (p1, p2) = (HashParams::from_seed(1), HashParams::from_seed(2));
+        assert_eq!(p1, HashParams::from_seed(1));
+        assert_ne!(p1, p2);
+        assert_ne!(p1.version(), p2.version());
+        assert_eq!(HashParams::default().version(), HashParams::DEFAULT.version());
+        assert!(!p1.version().contains(&format!("{:x}", p1.base))); // the version must not reveal a secret base
+
+        let chars = TEST_STR_2.chars().collect::<Vec<_>>();
+        let hash_with = |params: HashParams, s: &[char]| s.iter().fold(RollingHash::with_params(s.len(), params), |mut acc, c| { acc.push_char(*c); acc }).get_u64();
+        assert_eq!(hash_with(HashParams::DEFAULT, &chars), RollingHash::from_iter(chars.iter().copied()).get_u64());
+        assert_ne!(hash_with(p1, &chars), hash_with(HashParams::DEFAULT, &chars));
+
+        let w = 10;
+        let mut h = RollingHash::with_params(w, p1);
+        chars[..w - 1].iter().for_each(|c| h.push_char(*c));
+        for i in w - 1..chars.len() {
+            h.push_char(chars[i]);
+            assert_eq!(h.get_u64(), hash_with(p1, &chars[i + 1 - w..=i]));
+            h.pop_char(chars[i + 1 - w]);
+        }
+    }
+
+    /// Modular multiplication must agree with a plain 128-bit remainder
+    #[test]
+    fn test_mod_mul() {
+        let mut x: u64 = 12345;
+        for _ in 0..10000 {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let (a, b) = (x % MODULUS, (x >> 7) % MODULUS);
+            assert_eq!(mod_mul(a, b) as u128, a as u128 * b as u128 % MODULUS as u128);
+        }
+        assert_eq!(mod_mul(MODULUS - 1, MODULUS - 1), 1);
+        assert_eq!(mod_pow(BASE, 1000), (0..1000).fold(1, |acc, _| mod_mul(acc, BASE)));
+    }
+
+    /// Checks that hashes have the same equality properties as the previous 128-bit implementation (modulo the largest 64-bit prime)
+    #[test]
+    fn test_reference_equivalence() {
+        const REF_MODULUS: u128 = 18446744073709551557;
+        const REF_BASE: u128 = 11842660086381224053;
+        let reference = |s: &[char]| s.iter().fold(0u128, |acc, c| (acc * REF_BASE + *c as u128 + 1) % REF_MODULUS);
+
+        let chars = TEST_STR_2.chars().chain(TEST_STR_1.chars()).collect::<Vec<_>>();
+        for w in 1..=8 {
+            let hashes = chars.windows(w).map(|s| (reference(s), RollingHash::from_iter(s.iter().copied()).get_u64())).collect::<Vec<_>>();
+            for i in 0..hashes.len() {
+                for j in i+1..hashes.len() {
+                    assert_eq!(hashes[i].0 == hashes[j].0, hashes[i].1 == hashes[j].1);
+                }
+            }
+        }
+    }
+
+    /// Integer tokens must hash like the chars with the same code points, and must not be limited to valid chars
+    #[test]
+    fn test_tokens() {
+        let symbols = TEST_STR_2.chars().map(|c| c as u32).collect::<Vec<_>>();
+        assert_eq!(RollingHash::from_iter(symbols.iter().copied()).get_u64(), RollingHash::from_iter(TEST_STR_2.chars()).get_u64());
+        assert_eq!(RollingHash::<u64>::from_iter(symbols.iter().map(|s| *s as u64)).get_u64(), RollingHash::from_iter(TEST_STR_2.chars()).get_u64());
+
+        // Large symbols, including ones past the last valid char
+        let big = [0x110000u32, 0, u32::MAX, 0xE000 + 10000];
+        let hashes = (0..big.len()).map(|i| RollingHash::from_iter(big[i..].iter().copied()).get_u64()).collect::<Vec<_>>();
+        assert_eq!(hashes.iter().collect::<std::collections::HashSet<_>>().len(), big.len());
+        assert_ne!(RollingHash::from_iter([0u32].into_iter()).get_u64(), RollingHash::from_iter([0u32, 0].into_iter()).get_u64());
+
+        let mut h = RollingHash::<u32>::new(2);
+        h.push(u32::MAX);
+        h.push(7);
+        h.roll(u32::MAX, 9);
+        assert_eq!(h.get_u64(), RollingHash::from_iter([7u32, 9].into_iter()).get_u64());
+    }
+
+    /// Batch hashing must match rolling a single hash, including for inputs shorter than the number of lanes
+    #[test]
+    fn test_kgram_hashes() {
+        let chars = TEST_STR_2.chars().collect::<Vec<_>>();
+        for params in [HashParams::DEFAULT, HashParams::from_seed(3)] {
+            for len in (0..=20).chain([chars.len()]) {
+                for k in 1..=6 {
+                    let expected = if len < k { vec![] } else {
+                        chars[..len].windows(k).map(|w| w.iter().fold(RollingHash::with_params(k, params), |mut acc, c| { acc.push(*c); acc }).get_u64()).collect::<Vec<_>>()
+                    };
+                    assert_eq!(kgram_hashes_with_params(&chars[..len], k, params), expected);
+                }
+            }
+        }
+
+        assert!(kgram_hashes(&chars, 0).is_empty());
+        assert_eq!(kgram_hashes(&[1u32, 2, 3], 3), vec![RollingHash::from_iter([1u32, 2, 3].into_iter()).get_u64()]);
+    }
+
     /// Test clear functionality
     #[test]
     fn test_clear() {
         let test_str = String::from(TEST_STR_1);
 
-        let mut h1 = RollingHash::new();
+        let mut h1 = RollingHash::new(test_str.len());
         for c in test_str.chars() {
             h1.push_char(c);
         }
@@ -218,6 +523,7 @@ mod tests {
         h1.clear();
 
         assert_eq!(h1.len(), 0);
+        assert!(h1.is_empty());
         assert_eq!(h1.get_u64(), 0);
     }
 }
\ No newline at end of file
@@ -1,8 +1,11 @@
 // submodules
 mod preprocessor;
-mod fingerprint;
-mod matching;
+pub mod fingerprint;
+pub mod matching;
 mod logging;
-mod hash;
-mod fs;
-mod db;
\ No newline at end of file
+pub mod hash;
+pub mod fs;
+pub mod db;
+
+/// The language of a document, which appears in fingerprint headers and database results
+pub use preprocessor::langs::Lang;
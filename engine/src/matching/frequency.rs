@@ -0,0 +1,109 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::fingerprint::FingerprintSeq;
+
+/// How common a hash must be before it is suppressed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrequencyLimit {
+    /// Suppress hashes found in more than this many documents, like MOSS's `-m` option
+    Count(usize),
+    /// Suppress hashes found in more than this fraction of the documents
+    Fraction(f64),
+}
+
+impl FrequencyLimit {
+    /// Fails if the limit is a fraction that is NaN or negative
+    pub fn validate(self) -> Result<(), InvalidFraction> {
+        match self {
+            FrequencyLimit::Fraction(max) if max.is_nan() || max < 0.0 => Err(InvalidFraction(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The largest number of documents out of `n_documents` that a hash can be found in without being suppressed
+    pub fn max_documents(self, n_documents: usize) -> usize {
+        match self {
+            FrequencyLimit::Count(max) => max,
+            FrequencyLimit::Fraction(max) => (max * n_documents as f64).floor() as usize, // saturates for large fractions
+        }
+    }
+}
+
+/// Returned when a `FrequencyLimit::Fraction` is NaN or negative
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InvalidFraction(pub f64);
+
+impl std::fmt::Display for InvalidFraction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Frequency limit fraction {} must be a non-negative number", self.0)
+    }
+}
+
+impl std::error::Error for InvalidFraction {}
+
+/// A hash that was suppressed, and the number of documents it was found in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SuppressedHash {
+    pub hash: u64,
+    pub documents: usize,
+}
+
+/// Drops hashes that are found in too many documents of a corpus.  Such hashes are idioms (i.e. the signature of `main`, or a typical loop)
+/// rather than evidence of copying, and would otherwise make unrelated documents look similar.
+///
+/// The filter is built from the whole corpus, and then applied to each document's fingerprint before matching
+pub struct FrequencyFilter {
+    n_documents: usize,
+    suppressed: HashMap<u64, usize>,
+}
+
+impl FrequencyFilter {
+    /// Computes the document frequency of every hash in the corpus, and suppresses the hashes above `limit`.  A hash found several times in
+    /// one document counts once.  Fails if the limit is a fraction that is NaN or negative
+    pub fn new<'a>(corpus: impl IntoIterator<Item = &'a FingerprintSeq>, limit: FrequencyLimit) -> Result<Self, InvalidFraction> {
+        limit.validate()?;
+
+        let mut n_documents = 0;
+        let mut frequency = HashMap::new();
+        for fp in corpus {
+            n_documents += 1;
+            for hash in fp.iter().map(|(_, h)| *h).collect::<HashSet<_>>() {
+                *frequency.entry(hash).or_insert(0usize) += 1;
+            }
+        }
+
+        let max_documents = limit.max_documents(n_documents);
+        frequency.retain(|_, documents| *documents > max_documents);
+
+        Ok(Self { n_documents, suppressed: frequency })
+    }
+
+    /// The number of documents in the corpus
+    pub fn n_documents(&self) -> usize {
+        self.n_documents
+    }
+
+    /// Returns the number of documents a hash was found in if it is suppressed, or None if it is kept
+    pub fn suppressed_count(&self, hash: u64) -> Option<usize> {
+        self.suppressed.get(&hash).copied()
+    }
+
+    /// Removes the suppressed hashes from a fingerprint
+    pub fn apply(&self, fp: &FingerprintSeq) -> FingerprintSeq {
+        fp.iter().filter(|(_, h)| !self.suppressed.contains_key(h)).copied().collect()
+    }
+
+    /// Every suppressed hash, from most to least common
+    pub fn report(&self) -> Vec<SuppressedHash> {
+        let mut ret = self.suppressed.iter().map(|(hash, documents)| SuppressedHash { hash: *hash, documents: *documents }).collect::<Vec<_>>();
+        ret.sort_by(|x, y| y.documents.cmp(&x.documents).then(x.hash.cmp(&y.hash)));
+        ret
+    }
+
+    /// The suppressed hashes that two documents share, from most to least common, i.e. to explain why a match between them disappeared
+    pub fn report_shared(&self, a: &FingerprintSeq, b: &FingerprintSeq) -> Vec<SuppressedHash> {
+        let a_hashes = a.iter().map(|(_, h)| *h).collect::<HashSet<_>>();
+        let b_hashes = b.iter().map(|(_, h)| *h).collect::<HashSet<_>>();
+        self.report().into_iter().filter(|s| a_hashes.contains(&s.hash) && b_hashes.contains(&s.hash)).collect()
+    }
+}
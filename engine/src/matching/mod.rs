@@ -0,0 +1,91 @@
+//! Fine-grained matching between pairs of documents, done after fingerprinting.
+//!
+//! Similarity is measured by the fingerprint hashes two documents (or two segments of documents) share.  Since a copied segment can be a
+//! small part of a large file, similarity is reported in both directions: the fraction of the first document's fingerprints found in the
+//! second, and vice versa.
+//!
+//! Segments are found with `find_segments`.  Hashes that are common across the corpus can be removed with a `FrequencyFilter`, either
+//! beforehand with `FrequencyFilter::apply` or by passing it to `match_segments`.
+
+use std::collections::HashSet;
+
+use crate::fingerprint::FingerprintSeq;
+
+mod frequency;
+pub use frequency::{FrequencyFilter, FrequencyLimit, InvalidFraction, SuppressedHash};
+pub use crate::preprocessor::segments::{find_segments, Segment, SegmentKind};
+
+/// Similarity between two fingerprint sets
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Similarity {
+    /// Number of distinct hashes found in both
+    pub shared: usize,
+    /// Fraction of the first set's distinct hashes that are also in the second
+    pub a: f64,
+    /// Fraction of the second set's distinct hashes that are also in the first
+    pub b: f64,
+}
+
+impl Similarity {
+    /// The larger of the two directional similarities
+    pub fn max(&self) -> f64 {
+        self.a.max(self.b)
+    }
+}
+
+/// A pair of segments (one from each document) and how similar they are
+#[derive(Clone, PartialEq, Debug)]
+pub struct SegmentMatch {
+    pub a: Segment,
+    pub b: Segment,
+    pub similarity: Similarity,
+}
+
+fn hashes_in<'a>(fp: &'a FingerprintSeq, filter: impl Fn(usize) -> bool + 'a) -> HashSet<u64> {
+    fp.iter().filter(|(pos, _)| filter(*pos)).map(|(_, h)| *h).collect()
+}
+
+fn similarity_of(a: &HashSet<u64>, b: &HashSet<u64>) -> Similarity {
+    let shared = a.intersection(b).count();
+    let frac = |total: usize| if total == 0 { 0.0 } else { shared as f64 / total as f64 };
+    Similarity { shared, a: frac(a.len()), b: frac(b.len()) }
+}
+
+/// Computes the similarity between two whole documents
+pub fn similarity(a: &FingerprintSeq, b: &FingerprintSeq) -> Similarity {
+    similarity_of(&hashes_in(a, |_| true), &hashes_in(b, |_| true))
+}
+
+/// Computes the similarity between every pair of segments of two documents, i.e. to report that "`solve()` in A matches `work()` in B at
+/// 92%".  Pairs whose larger directional similarity is below `min_similarity` are dropped, and the rest are sorted from most to least similar.
+/// Hashes suppressed by `filter` are ignored, so segments that only share idioms do not match.
+///
+/// Fingerprints are assigned to a segment by their starting position, so a fingerprint in a method also counts towards its class
+pub fn match_segments(a_fp: &FingerprintSeq, a_segments: &[Segment], b_fp: &FingerprintSeq, b_segments: &[Segment], min_similarity: f64,
+    filter: Option<&FrequencyFilter>) -> Vec<SegmentMatch> {
+    let segment_hashes = |fp: &FingerprintSeq, s: &Segment| {
+        let mut hashes = hashes_in(fp, |pos| s.contains(pos));
+        if let Some(filter) = filter {
+            hashes.retain(|h| filter.suppressed_count(*h).is_none());
+        }
+        hashes
+    };
+    let a_hashes = a_segments.iter().map(|s| segment_hashes(a_fp, s)).collect::<Vec<_>>();
+    let b_hashes = b_segments.iter().map(|s| segment_hashes(b_fp, s)).collect::<Vec<_>>();
+
+    let mut ret = vec![];
+    for (a, a_set) in a_segments.iter().zip(&a_hashes) {
+        for (b, b_set) in b_segments.iter().zip(&b_hashes) {
+            let similarity = similarity_of(a_set, b_set);
+            if similarity.shared > 0 && similarity.max() >= min_similarity {
+                ret.push(SegmentMatch { a: a.clone(), b: b.clone(), similarity });
+            }
+        }
+    }
+
+    ret.sort_by(|x, y| y.similarity.max().total_cmp(&x.similarity.max()));
+    ret
+}
+
+#[cfg(test)]
+mod tests;
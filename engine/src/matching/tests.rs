@@ -0,0 +1,83 @@
+use super::*;
+
+fn function(name: &str, start: usize, end: usize) -> Segment {
+    Segment { kind: SegmentKind::Function, name: String::from(name), start, end }
+}
+
+#[test]
+fn whole_document_similarity() {
+    let a = vec![(0, 1), (5, 2), (9, 3), (12, 4)];
+    let b = vec![(3, 3), (4, 4), (8, 5), (10, 3)];
+
+    let sim = similarity(&a, &b);
+    assert_eq!(sim.shared, 2);
+    assert_eq!(sim.a, 0.5);
+    assert_eq!(sim.b, 2.0 / 3.0);
+    assert_eq!(similarity(&a, &vec![]), Similarity { shared: 0, a: 0.0, b: 0.0 });
+}
+
+#[test]
+fn segment_similarity() {
+    // A: `solve` is copied into B's `work`, `helper` is original
+    let a_fp = vec![(0, 10), (4, 11), (8, 12), (20, 13), (24, 14)];
+    let a_segments = vec![function("solve", 0, 10), function("helper", 18, 30)];
+    let b_fp = vec![(0, 99), (30, 10), (33, 11), (36, 12), (37, 98), (50, 14), (52, 97)];
+    let b_segments = vec![function("main", 0, 20), function("work", 25, 40), function("other", 45, 55)];
+
+    let matches = match_segments(&a_fp, &a_segments, &b_fp, &b_segments, 0.0, None);
+    let summary = matches.iter().map(|m| (m.a.name.as_str(), m.b.name.as_str(), m.similarity.shared)).collect::<Vec<_>>();
+    assert_eq!(summary, vec![("solve", "work", 3), ("helper", "other", 1)]);
+    assert_eq!(matches[0].similarity.a, 1.0);
+    assert_eq!(matches[0].similarity.b, 0.75);
+
+    let matches = match_segments(&a_fp, &a_segments, &b_fp, &b_segments, 0.9, None);
+    assert_eq!(matches.len(), 1);
+}
+
+#[test]
+fn frequency_filter() {
+    // 1 is in every document (i.e. the signature of `main`), 2 is in most of them, 3 is shared by one pair
+    let corpus: Vec<FingerprintSeq> = vec![
+        vec![(0, 1), (4, 2), (8, 3), (9, 3)],
+        vec![(0, 1), (4, 2), (8, 3)],
+        vec![(0, 1), (4, 2), (7, 4)],
+        vec![(0, 1), (5, 5)],
+    ];
+
+    let filter = FrequencyFilter::new(&corpus, FrequencyLimit::Count(2)).unwrap();
+    assert_eq!(filter.n_documents(), 4);
+    assert_eq!(filter.report(), vec![SuppressedHash { hash: 1, documents: 4 }, SuppressedHash { hash: 2, documents: 3 }]);
+    assert_eq!(filter.suppressed_count(2), Some(3));
+    assert_eq!(filter.suppressed_count(3), None); // repeated in one document, but only counted once
+    assert_eq!(filter.apply(&corpus[0]), vec![(8, 3), (9, 3)]);
+    assert_eq!(filter.apply(&corpus[3]), vec![(5, 5)]);
+
+    // Documents 2 and 3 only shared idioms, so their match disappears
+    assert_eq!(similarity(&filter.apply(&corpus[2]), &filter.apply(&corpus[3])).shared, 0);
+    assert_eq!(filter.report_shared(&corpus[2], &corpus[3]), vec![SuppressedHash { hash: 1, documents: 4 }]);
+
+    let filter = FrequencyFilter::new(&corpus, FrequencyLimit::Fraction(0.8)).unwrap();
+    assert_eq!(filter.report(), vec![SuppressedHash { hash: 1, documents: 4 }]);
+    let filter = FrequencyFilter::new(&corpus, FrequencyLimit::Fraction(1.0)).unwrap();
+    assert!(filter.report().is_empty());
+    assert_eq!(FrequencyFilter::new(&corpus, FrequencyLimit::Fraction(-0.5)).err(), Some(InvalidFraction(-0.5)));
+    assert!(FrequencyFilter::new(&corpus, FrequencyLimit::Fraction(f64::NAN)).is_err());
+}
+
+#[test]
+fn segment_similarity_filtered() {
+    // `main` in both documents only shares the idiom 1, which is in every document of the corpus
+    let a_fp = vec![(0, 1), (4, 10), (8, 11)];
+    let b_fp = vec![(0, 1), (30, 10), (33, 11)];
+    let corpus = vec![a_fp.clone(), b_fp.clone(), vec![(0, 1)]];
+    let a_segments = vec![function("main", 0, 3), function("solve", 4, 10)];
+    let b_segments = vec![function("main", 0, 3), function("work", 30, 40)];
+
+    let names = |matches: Vec<SegmentMatch>| matches.iter().map(|m| (m.a.name.clone(), m.b.name.clone())).collect::<Vec<_>>();
+    let unfiltered = match_segments(&a_fp, &a_segments, &b_fp, &b_segments, 0.0, None);
+    assert_eq!(names(unfiltered), vec![(String::from("main"), String::from("main")), (String::from("solve"), String::from("work"))]);
+
+    let filter = FrequencyFilter::new(&corpus, FrequencyLimit::Count(2)).unwrap();
+    let filtered = match_segments(&a_fp, &a_segments, &b_fp, &b_segments, 0.0, Some(&filter));
+    assert_eq!(names(filtered), vec![(String::from("solve"), String::from("work"))]);
+}
@@ -0,0 +1,143 @@
+//! An alternative to the char-level preprocessors in `langs`, which parses the document with a tree-sitter grammar and emits its syntax tree
+//! as a linear sequence of nodes.
+//!
+//! The char-level steps cannot tell a parameter from a type name or a call from a declaration, and are thrown off by changes in bracket style
+//! or redundant parentheses.  Working on the syntax tree avoids these problems:
+//! - Every identifier becomes the kind of name it is (`identifier`, `type_identifier`, `field_identifier`, ...), so renaming has no effect
+//!   but the role of each name is kept
+//! - Parenthesized expressions and blocks with a single statement are unwrapped, so `if (x) { y(); }` and `if (x) y();` are the same
+//! - Punctuation and keywords are dropped (they are implied by the node kinds), and comments are skipped entirely
+//!
+//! Only available with the `ast` feature, which compiles the C/C++, Java and Python grammars vendored in `vendor/`, so it needs a C compiler.
+//! C is parsed with the C++ grammar, the same way `Lang::Cpp` covers both.  The mode is selected with `PreprocessorOptions::ast`, which
+//! replaces the char-level steps with the `Ast` step.
+
+extern crate tree_sitter;
+
+use tree_sitter::{Language, Node, Parser};
+
+use super::{langs::Lang, Seq, Step, Symbol};
+
+// Defined by the vendored parsers, see `build.rs`
+extern "C" {
+    fn tree_sitter_cpp() -> Language;
+    fn tree_sitter_java() -> Language;
+    fn tree_sitter_python() -> Language;
+}
+
+/// A node of the linearized syntax tree.  `start..end` is the range of chars it spans in the original document
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AstNode {
+    pub kind: &'static str,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Nodes that are never emitted themselves, only their children
+const TRANSPARENT_KINDS: [&str; 1] = ["parenthesized_expression"];
+/// Blocks, which are unwrapped if they hold a single statement
+const BLOCK_KINDS: [&str; 2] = ["compound_statement", "block"];
+/// Anonymous tokens that only delimit other nodes
+const PUNCTUATION: [&str; 9] = ["(", ")", "[", "]", "{", "}", ";", ",", ":"];
+
+fn language(lang: Lang) -> Option<Language> {
+    // Safety: the functions only return a pointer to the static grammar tables of the parser
+    unsafe {
+        match lang {
+            Lang::Cpp => Some(tree_sitter_cpp()),
+            Lang::Java => Some(tree_sitter_java()),
+            // Notebooks are preprocessed through the joined source of their code cells, see `notebook::Notebook`
+            Lang::Python | Lang::Notebook => Some(tree_sitter_python()),
+            Lang::Text | Lang::Generic => None,
+        }
+    }
+}
+
+/// Checks whether a language has a grammar, i.e. whether `linearize` and `preprocess` support it
+pub fn is_supported(lang: Lang) -> bool {
+    language(lang).is_some()
+}
+
+/// Decides whether a node is emitted, and whether its children are visited
+fn visit(node: &Node) -> (bool, bool) {
+    let kind = node.kind();
+    if node.is_extra() { // comments
+        (false, false)
+    }
+    else if TRANSPARENT_KINDS.contains(&kind) || (BLOCK_KINDS.contains(&kind) && node.named_child_count() == 1) {
+        (false, true)
+    }
+    else if node.is_named() {
+        (true, true)
+    }
+    else { // anonymous tokens: only operators are kept, since keywords are implied by the kind of their parent
+        (!PUNCTUATION.contains(&kind) && !kind.chars().any(|c| c.is_alphanumeric()), false)
+    }
+}
+
+/// Parses a document and returns its syntax tree as a sequence of nodes in pre-order.  Returns None if the language has no grammar.
+///
+/// Syntax errors do not cause a failure: tree-sitter recovers from them, and the `ERROR` nodes it produces are emitted like any other node
+pub fn linearize(lang: Lang, source: &str) -> Option<Vec<AstNode>> {
+    let mut parser = Parser::new();
+    parser.set_language(language(lang)?).ok()?;
+    let tree = parser.parse(source, None)?;
+
+    // tree-sitter works with byte offsets, but positions in the engine are char offsets
+    let mut char_offset = vec![0; source.len() + 1];
+    for (i, (byte, _)) in source.char_indices().enumerate() {
+        char_offset[byte] = i;
+    }
+    char_offset[source.len()] = source.chars().count();
+
+    // The tree is walked with a cursor instead of recursion, since deeply nested code could otherwise overflow the stack
+    let mut ret = vec![];
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        let (emit, descend) = visit(&node);
+        if emit {
+            ret.push(AstNode { kind: node.kind(), start: char_offset[node.start_byte()], end: char_offset[node.end_byte()] });
+        }
+
+        if descend && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+
+    Some(ret)
+}
+
+/// Runs the AST-based preprocessor, which can be used in place of `apply` with a preprocessor from `langs`.  Each node becomes one token
+/// named by its kind (see `Symbol::token`), positioned at the start of the node.  Returns None if the language has no grammar
+pub fn preprocess(lang: Lang, source: &str) -> Option<Seq> {
+    Some(linearize(lang, source)?.into_iter().map(|node| (node.start, Symbol::token(node.kind))).collect())
+}
+
+/// A step that replaces the document with its linearized syntax tree (see `preprocess`).  It parses the document itself, so it must be the
+/// only step, applied to the chars of the original document
+pub struct Ast {
+    lang: Lang,
+}
+
+impl Ast {
+    /// Returns None if the language has no grammar
+    pub fn new(lang: Lang) -> Option<Self> {
+        is_supported(lang).then_some(Self { lang })
+    }
+}
+
+impl Step for Ast {
+    fn apply(&self, input: &Seq) -> Seq {
+        let source = input.iter().map(|c| c.1.as_char().unwrap_or(char::REPLACEMENT_CHARACTER)).collect::<String>();
+        // Empty nodes at the end of the document start just past the last char
+        preprocess(self.lang, &source).unwrap_or_default().into_iter()
+            .filter_map(|(i, c)| input.get(i).or(input.last()).map(|p| (p.0, c)))
+            .collect()
+    }
+}
@@ -3,11 +3,16 @@ use crate::preprocessor::steps;
 
 // Reference: https://en.cppreference.com/w/cpp/keyword
 pub fn get_preprocessor() -> Preprocessor {
+    get_preprocessor_with_options(&[], false)
+}
+
+/// `drop_directives` is passed on to `steps::Macros`
+pub fn get_preprocessor_with_options(extra: &[&str], drop_directives: bool) -> Preprocessor {
     vec![
         // Comments
         Box::new(steps::Comment::new(
-            "//",
-            ("/*", "*/"),
+            vec!["//"],
+            vec![("/*", "*/", false)],
         )),
         // Digraphs
         Box::new(steps::SameSizeReplace::new(
@@ -33,11 +38,36 @@ pub fn get_preprocessor() -> Preprocessor {
                 ("??-", '~'),
             ]
         )),
-        Box::new(steps::Identifiers::new(keywords(), 'I')),
+        // Macros and type aliases (must run after trigraphs so that `??=define` is seen as a directive)
+        Box::new(steps::Macros::new(drop_directives)),
+        // Imports and other boilerplate
+        Box::new(steps::Boilerplate::new(boilerplate().into_iter().chain(extra.iter().copied()).collect())),
+        Box::new(steps::Identifiers::new(keywords(), steps::IdentifierGrammar::C_LIKE, 'I')),
         Box::new(steps::Whitespace::new())
     ]
 }
 
+#[inline(always)]
+pub(crate) fn boilerplate() -> Vec<&'static str> {
+    vec![
+        "#include",
+        "using namespace std;",
+        "ios::sync_with_stdio",
+        "ios_base::sync_with_stdio",
+        "std::ios::sync_with_stdio",
+        "std::ios_base::sync_with_stdio",
+        "cin.tie",
+        "cout.tie",
+        "std::cin.tie",
+        "std::cout.tie",
+    ]
+}
+
+#[inline(always)]
+pub(crate) fn reorder() -> steps::Reorder {
+    steps::Reorder::braces(1)
+}
+
 #[inline(always)]
 pub(crate) fn keywords() -> Vec<&'static str> {
     vec![
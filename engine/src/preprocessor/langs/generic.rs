@@ -0,0 +1,61 @@
+use crate::preprocessor::Preprocessor;
+use crate::preprocessor::steps;
+
+// Fallback for files whose language is not recognized.  Since the language is unknown, comments are removed using the most common
+// styles, and only a small set of keywords shared by most C-like and scripting languages is kept apart from other identifiers.
+pub fn get_preprocessor() -> Preprocessor {
+    get_preprocessor_with_boilerplate(&[])
+}
+
+/// There is no default boilerplate for unknown languages, so only the extra patterns are stripped
+pub fn get_preprocessor_with_boilerplate(extra: &[&str]) -> Preprocessor {
+    vec![
+        // Comments (C-style, shell/scripting, SQL/Lua/Haskell and markup).  `--` needs a trailing space to not eat `i--`
+        Box::new(steps::Comment::new(
+            vec!["//", "#", "-- "],
+            vec![
+                ("/*", "*/", false),
+                ("<!--", "-->", false),
+            ],
+        )),
+        Box::new(steps::Boilerplate::new(extra.to_vec())),
+        Box::new(steps::Identifiers::new(keywords(), steps::IdentifierGrammar::JAVA, 'I')),
+        Box::new(steps::Whitespace::new())
+    ]
+}
+
+#[inline(always)]
+pub(crate) fn reorder() -> steps::Reorder {
+    steps::Reorder::braces(1)
+}
+
+#[inline(always)]
+pub(crate) fn keywords() -> Vec<&'static str> {
+    vec![
+        "break",
+        "case",
+        "catch",
+        "class",
+        "continue",
+        "def",
+        "default",
+        "do",
+        "else",
+        "false",
+        "fn",
+        "for",
+        "func",
+        "function",
+        "if",
+        "in",
+        "new",
+        "null",
+        "return",
+        "struct",
+        "switch",
+        "this",
+        "true",
+        "try",
+        "while",
+    ]
+}
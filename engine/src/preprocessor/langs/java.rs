@@ -3,19 +3,38 @@ use crate::preprocessor::Preprocessor;
 
 // Keywords from: https://docs.oracle.com/javase/tutorial/java/nutsandbolts/_keywords.html
 pub fn get_preprocessor() -> Preprocessor {
+    get_preprocessor_with_boilerplate(&[])
+}
+
+pub fn get_preprocessor_with_boilerplate(extra: &[&str]) -> Preprocessor {
     vec![
         // Comments
         Box::new(steps::Comment::new(
-            "//",
-            ("/*", "*/"),
+            vec!["//"],
+            vec![("/*", "*/", false)],
         )),
+        // Imports and other boilerplate
+        Box::new(steps::Boilerplate::new(boilerplate().into_iter().chain(extra.iter().copied()).collect())),
         // IDs
         // Remove keywords that are typenames
-        Box::new(steps::Identifiers::new(keywords(), 'I')),
+        Box::new(steps::Identifiers::new(keywords(), steps::IdentifierGrammar::JAVA, 'I')),
         Box::new(steps::Whitespace::new()),
     ]
 }
 
+#[inline(always)]
+pub(crate) fn boilerplate() -> Vec<&'static str> {
+    vec![
+        "package",
+        "import",
+    ]
+}
+
+#[inline(always)]
+pub(crate) fn reorder() -> steps::Reorder {
+    steps::Reorder::braces(2) // top-level classes, and the members of each class
+}
+
 #[inline(always)]
 pub(crate) fn keywords() -> Vec<&'static str> {
     vec![
@@ -1,25 +1,48 @@
-use crate::preprocessor::Preprocessor;
+use crate::preprocessor::{Preprocessor, Symbol};
 use crate::preprocessor::steps;
 
 // Reference: https://docs.python.org/3/library/keyword.html
 // On CPython 3.9.13 (Windows 11), the following keywords are reserved:
 // ['False', 'None', 'True', '__peg_parser__', 'and', 'as', 'assert', 'async', 'await', 'break', 'class', 'continue', 'def', 'del', 'elif', 'else', 'except', 'finally', 'for', 'from', 'global', 'if', 'import', 'in', 'is', 'lambda', 'nonlocal', 'not', 'or', 'pass', 'raise', 'return', 'try', 'while', 'with', 'yield']
 pub fn get_preprocessor() -> Preprocessor {
+    get_preprocessor_with_boilerplate(&[])
+}
+
+pub fn get_preprocessor_with_boilerplate(extra: &[&str]) -> Preprocessor {
     vec![
-        // Comments (2 types)
-        Box::new(steps::Comment::new(
-            "#",
-            ("\"\"\"", "\"\"\""),
-        )),
+        // Comments (docstrings are treated as comments, in both quote styles)
         Box::new(steps::Comment::new(
-            "#", // can be anything, really
-            ("'''", "'''"),
+            vec!["#"],
+            vec![
+                ("\"\"\"", "\"\"\"", false),
+                ("'''", "'''", false),
+            ],
         )),
-        Box::new(steps::Identifiers::new(keywords(), 'I')),
+        // Imports and other boilerplate
+        Box::new(steps::Boilerplate::new(boilerplate().into_iter().chain(extra.iter().copied()).collect())),
+        Box::new(steps::Identifiers::new(keywords(), steps::IdentifierGrammar::C_LIKE, 'I')),
         Box::new(steps::Whitespace::new())
     ]
 }
 
+#[inline(always)]
+pub(crate) fn boilerplate() -> Vec<&'static str> {
+    vec![
+        "import",
+        "from",
+        "input = sys.stdin.readline",
+        "input = lambda: sys.stdin.readline",
+        "print = sys.stdout.write",
+    ]
+}
+
+#[inline(always)]
+pub(crate) fn reorder() -> steps::Reorder {
+    let tokens = |kws: &[&str]| kws.iter().map(|kw| Symbol::token(kw)).collect::<Vec<_>>();
+    let definitions = [tokens(&["def", "class", "async", "import", "from"]), vec!['@'.into()]].concat();
+    steps::Reorder::indentation(tokens(&["elif", "else", "except", "finally"]), definitions)
+}
+
 #[inline(always)]
 pub(crate) fn keywords() -> Vec<&'static str> {
     vec![
@@ -0,0 +1,151 @@
+use crate::preprocessor::Preprocessor;
+use crate::preprocessor::steps;
+
+// Natural-language text (essays, lab reports), normalized as described in the winnowing paper: case folding and removing punctuation and
+// whitespace.  Matching is done on whole words, optionally ignoring stop words.
+pub fn get_preprocessor() -> Preprocessor {
+    get_preprocessor_with_stop_words(stop_words())
+}
+
+/// Passing an empty list disables stop word removal
+pub fn get_preprocessor_with_stop_words(stop_words: Vec<&str>) -> Preprocessor {
+    vec![
+        Box::new(steps::Lowercase::new()),
+        Box::new(steps::Punctuation::new()),
+        Box::new(steps::Words::new(stop_words)),
+    ]
+}
+
+// Stop words are matched after case folding and punctuation removal, which is why i.e. "dont" has no apostrophe
+#[inline(always)]
+pub(crate) fn stop_words() -> Vec<&'static str> {
+    vec![
+        "a",
+        "about",
+        "above",
+        "after",
+        "again",
+        "against",
+        "all",
+        "am",
+        "an",
+        "and",
+        "any",
+        "are",
+        "as",
+        "at",
+        "be",
+        "because",
+        "been",
+        "before",
+        "being",
+        "below",
+        "between",
+        "both",
+        "but",
+        "by",
+        "can",
+        "could",
+        "did",
+        "do",
+        "does",
+        "doing",
+        "dont",
+        "down",
+        "during",
+        "each",
+        "few",
+        "for",
+        "from",
+        "further",
+        "had",
+        "has",
+        "have",
+        "having",
+        "he",
+        "her",
+        "here",
+        "hers",
+        "herself",
+        "him",
+        "himself",
+        "his",
+        "how",
+        "i",
+        "if",
+        "in",
+        "into",
+        "is",
+        "it",
+        "its",
+        "itself",
+        "just",
+        "me",
+        "more",
+        "most",
+        "my",
+        "myself",
+        "no",
+        "nor",
+        "not",
+        "now",
+        "of",
+        "off",
+        "on",
+        "once",
+        "only",
+        "or",
+        "other",
+        "our",
+        "ours",
+        "ourselves",
+        "out",
+        "over",
+        "own",
+        "same",
+        "she",
+        "should",
+        "so",
+        "some",
+        "such",
+        "than",
+        "that",
+        "the",
+        "their",
+        "theirs",
+        "them",
+        "themselves",
+        "then",
+        "there",
+        "these",
+        "they",
+        "this",
+        "those",
+        "through",
+        "to",
+        "too",
+        "under",
+        "until",
+        "up",
+        "very",
+        "was",
+        "we",
+        "were",
+        "what",
+        "when",
+        "where",
+        "which",
+        "while",
+        "who",
+        "whom",
+        "why",
+        "will",
+        "with",
+        "would",
+        "you",
+        "your",
+        "yours",
+        "yourself",
+        "yourselves",
+    ]
+}
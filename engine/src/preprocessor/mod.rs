@@ -11,9 +11,74 @@
 //! It may seem a bit weird to put the language-resolution code in here, but remember that MOSS is designed to be language-agnostic, so any language-specific
 //! processing should be done in the preprocessor.
 
-/// In the preprocessor, strings are treated as pairs of (index, char) where the element s[i] denotes that the substring from indices
+/// In the preprocessor, strings are treated as pairs of (index, symbol) where the element s[i] denotes that the substring from indices
 /// s[i].0 to s[i+1].0-1 (inclusive) is compressed as s[i].1.  This is both crucial in intermediate steps and in the final result
-pub type Seq = Vec<(usize, char)>;
+pub type Seq = Vec<(usize, Symbol)>;
+
+/// Symbols at or above this value are tokens (see `Symbol::token`), and every value below it that is a valid char is that char
+const TOKEN_OFF: u64 = 1 << 32;
+/// Tokens are kept below 2^61 - 2, so that `RollingHash<u64>` gives every symbol a distinct value
+const TOKEN_END: u64 = (1 << 61) - 2;
+
+/// An element of a preprocessed sequence: either a single char of the document, or a multi-character token (i.e. a keyword, a word or an
+/// operator) collapsed into one symbol.  Chars keep their code point as their value, so a sequence of chars hashes the same way as the chars
+/// themselves
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Symbol(u64);
+
+impl Symbol {
+    /// Maps a multi-character token to a symbol by hashing it, so the same token always maps to the same symbol across documents, runs and
+    /// machines (which an interner could not guarantee).  Tokens never collide with chars, and with 2^61 possible values, distinct tokens
+    /// only collide with probability ~n^2 * 2^(-62) in a vocabulary of n tokens
+    pub fn token(token: &str) -> Self {
+        let h = token.chars().collect::<crate::hash::RollingHash>().get_u64();
+        Self(TOKEN_OFF + h % (TOKEN_END - TOKEN_OFF))
+    }
+
+    /// Returns the char this symbol stands for, or None if it is a token
+    pub fn as_char(self) -> Option<char> {
+        u32::try_from(self.0).ok().and_then(char::from_u32)
+    }
+
+    pub fn is_whitespace(self) -> bool {
+        self.as_char().is_some_and(char::is_whitespace)
+    }
+
+    pub fn is_alphanumeric(self) -> bool {
+        self.as_char().is_some_and(char::is_alphanumeric)
+    }
+
+    pub fn is_ascii_digit(self) -> bool {
+        self.as_char().is_some_and(|c| c.is_ascii_digit())
+    }
+
+    pub fn is_ascii_hexdigit(self) -> bool {
+        self.as_char().is_some_and(|c| c.is_ascii_hexdigit())
+    }
+}
+
+impl From<char> for Symbol {
+    fn from(c: char) -> Self {
+        Self(c as u64)
+    }
+}
+
+impl From<Symbol> for u64 {
+    fn from(s: Symbol) -> Self {
+        s.0
+    }
+}
+
+impl PartialEq<char> for Symbol {
+    fn eq(&self, c: &char) -> bool {
+        self.0 == *c as u64
+    }
+}
+
+/// Returns the text of a run of a sequence, or None if it contains tokens
+pub(crate) fn text_of(seq: &[(usize, Symbol)]) -> Option<String> {
+    seq.iter().map(|(_, s)| s.as_char()).collect()
+}
 
 /// A single step in a preprocessor that transforms a sequence of characters
 pub trait Step {
@@ -25,10 +90,61 @@ type Preprocessor = Vec<Box<dyn Step>>;
 
 /// Applies a preprocessor to a string
 pub fn apply(input: &str, steps: &Preprocessor) -> Seq {
-    let init_seq: Seq = input.chars().enumerate().collect();
+    let init_seq: Seq = input.chars().enumerate().map(|(i, c)| (i, c.into())).collect();
     steps.iter().fold(init_seq, |cur_seq, step| { step.apply(&cur_seq) })
 }
 
+/// Multi-character operators across the supported languages, which `tokenize` groups into a single token.  Longer operators come first, so
+/// the longest match wins
+const OPERATORS: [&str; 37] = [
+    ">>>=",
+    "<<=", ">>=", ">>>", "<=>", "->*", "...", "**=", "//=", "===", "!==",
+    "<=", ">=", "==", "!=", "&&", "||", "<<", ">>", "->", "::", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "**", "//",
+    ".*", "=>", ":=", "@=",
+];
+
+/// Groups a preprocessed sequence into tokens, so that k-grams can be measured in tokens instead of characters.  This makes the same
+/// `k` and `t` mean roughly the same thing across languages, regardless of how long each language's normalized tokens are or how many
+/// multi-character operators they use.
+///
+/// After preprocessing, identifiers and keywords are already single symbols, so the multi-character tokens left are:
+/// - Alphanumeric runs that were contiguous in the original document, i.e. numeric literals such as `0x1F` or `2.5e-3`
+/// - Operators in `OPERATORS` whose characters were contiguous in the original document, i.e. `<=` but not `< =`
+///
+/// These are collapsed into a single symbol using `Symbol::token`, and every other symbol is its own token.  The result is a `Seq` with
+/// one element per token
+pub fn tokenize(seq: &Seq) -> Seq {
+    let contiguous = |i: usize, len: usize| i + len <= seq.len() && (i + 1..i + len).all(|j| seq[j].0 == seq[j - 1].0 + 1);
+
+    let mut ret = vec![];
+    let mut i = 0;
+
+    while i < seq.len() {
+        let number = seq[i].1.is_ascii_digit();
+        let mut j = i + 1;
+        if seq[i].1.is_alphanumeric() {
+            while j < seq.len() && seq[j].0 == seq[j - 1].0 + 1 {
+                let (Some(prev), Some(c)) = (seq[j - 1].1.as_char(), seq[j].1.as_char()) else { break };
+                let number_char = number && (matches!(c, '.' | '_' | '\'') || (matches!(c, '+' | '-') && matches!(prev, 'e' | 'E' | 'p' | 'P')));
+                if !c.is_alphanumeric() && !number_char {
+                    break;
+                }
+                j += 1;
+            }
+        }
+        else if let Some(op) = OPERATORS.iter().find(|op| contiguous(i, op.len()) && op.chars().zip(&seq[i..]).all(|(a, b)| b.1 == a)) {
+            j = i + op.len();
+        }
+
+        // Runs of more than one symbol are made of chars only, since tokens are neither alphanumeric nor part of an operator
+        let symbol = if j - i == 1 { seq[i].1 } else { Symbol::token(&text_of(&seq[i..j]).unwrap()) };
+        ret.push((seq[i].0, symbol));
+        i = j;
+    }
+
+    ret
+}
+
 /// Different preprocessor steps that can be applied in sequence to form a full preprocessor
 mod steps {
     #[cfg(not(test))]
@@ -36,11 +152,23 @@ mod steps {
     mod whitespace;
     mod replace;
     mod comment;
+    mod macros;
+    mod boilerplate;
+    mod case;
+    mod punctuation;
+    mod words;
+    mod reorder;
 
-    pub use identifiers::Identifiers;
+    pub use identifiers::{Identifiers, IdentifierGrammar};
     pub use whitespace::Whitespace;
     pub use replace::SameSizeReplace;
     pub use comment::Comment;
+    pub use macros::Macros;
+    pub use boilerplate::Boilerplate;
+    pub use case::Lowercase;
+    pub use punctuation::Punctuation;
+    pub use words::Words;
+    pub use reorder::Reorder;
 
     #[cfg(test)]
     pub mod identifiers;
@@ -49,10 +177,17 @@ mod steps {
 /// Preprocessor implementations for various languages 
 /// Also defines language module to match 
 pub mod langs {
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
     pub enum Lang {
         Java,
         Cpp,
         Python,
+        /// Jupyter notebooks, which are preprocessed as Python after extracting their code cells (see `notebook::Notebook`)
+        Notebook,
+        /// Natural-language text, such as essays and reports
+        Text,
+        /// Fallback for text files in languages that are not otherwise supported
+        Generic,
     }
 
     impl Lang {
@@ -70,32 +205,121 @@ pub mod langs {
                 "cc" => Some(Lang::Cpp),
                 "cpp" => Some(Lang::Cpp),
                 "py" => Some(Lang::Python),
+                "ipynb" => Some(Lang::Notebook),
+                "txt" | "md" | "tex" => Some(Lang::Text),
                 _ => None,
             }
         }
 
-        fn to_ext(&self) -> &'static str {
+        /// Inverse of `to_ext`
+        pub(crate) fn from_normalized_ext(s: &str) -> Option<Lang> {
+            [Lang::Java, Lang::Cpp, Lang::Python, Lang::Notebook, Lang::Text, Lang::Generic].into_iter().find(|lang| lang.to_ext() == s)
+        }
+
+        /// The normalized file extension of the language, which is also how it is named in the database (and in results, i.e. "generic")
+        pub(crate) fn to_ext(self) -> &'static str {
             match self {
                 Lang::Java => "java",
                 Lang::Cpp => "cpp",
                 Lang::Python => "py",
+                Lang::Notebook => "ipynb",
+                Lang::Text => "txt",
+                Lang::Generic => "generic",
             }
         }
     }
 
+    /// Returns whether file contents look like binary data rather than text, i.e. they contain NUL bytes or mostly control characters.
+    /// This works on raw bytes, since binaries are usually not valid UTF-8.  Text in other encodings (i.e. Latin-1) is still text
+    fn is_binary(contents: &[u8]) -> bool {
+        let sample = &contents[..contents.len().min(8192)];
+        if sample.contains(&0) {
+            return true;
+        }
+        let control = sample.iter().filter(|b| b.is_ascii_control() && !b.is_ascii_whitespace()).count();
+        control * 10 > sample.len()
+    }
+
+    /// Resolves the language of a file based on its path, falling back to `Lang::Generic` for unrecognized text files.
+    /// Binary files are skipped (with a logged reason), in which case None is returned
+    pub fn resolve(path: &std::path::Path, contents: &[u8]) -> Option<Lang> {
+        if let Some(lang) = path.extension().and_then(|ext| ext.to_str()).and_then(Lang::from_ext) {
+            return Some(lang);
+        }
+
+        if is_binary(contents) {
+            log::warn!("Skipping {}: unrecognized extension and contents look like binary data", path.display());
+            None
+        }
+        else {
+            Some(Lang::Generic)
+        }
+    }
+
     #[cfg(not(test))]
     mod java;
     #[cfg(not(test))]
     mod cpp;
     #[cfg(not(test))]
     mod python;
+    #[cfg(not(test))]
+    mod text;
+    #[cfg(not(test))]
+    mod generic;
+
+    /// Options for building a preprocessor with `get_preprocessor_with_options`.  The defaults give the same preprocessor as `get_preprocessor`
+    #[derive(Clone, Default, Debug)]
+    pub struct PreprocessorOptions<'a> {
+        /// Extra boilerplate patterns to strip on top of the language's defaults.  See `steps::Boilerplate` for how patterns are matched.
+        /// Ignored for text
+        pub boilerplate: &'a [&'a str],
+        /// Text only: extra stop words to remove on top of the defaults (see `steps::Words`)
+        pub stop_words: &'a [&'a str],
+        /// C/C++ only: also remove `#include` and `#pragma` lines (see `steps::Macros`)
+        pub drop_directives: bool,
+        /// Also sort top-level definitions into a canonical order, so that reordering functions and declarations does not break up matches
+        /// (see `steps::Reorder`).  Ignored for text
+        pub canonical: bool,
+        /// Use the AST-based preprocessor (see `ast::Ast`) instead of the char-level steps, in which case the other options are ignored.
+        /// Languages without a grammar (see `ast::is_supported`) still use the char-level steps
+        #[cfg(feature = "ast")]
+        pub ast: bool,
+    }
 
     pub fn get_preprocessor(lang: Lang) -> super::Preprocessor {
-        match lang {
-            Lang::Java => java::get_preprocessor(),
-            Lang::Cpp => cpp::get_preprocessor(),
-            Lang::Python => python::get_preprocessor(),
+        get_preprocessor_with_options(lang, &PreprocessorOptions::default())
+    }
+
+    /// Notebooks are JSON documents, so the preprocessor for `Lang::Notebook` is the Python one, and applies to the joined source of their code
+    /// cells (see `notebook::Notebook::source`) rather than to the file itself.  The same goes for the other `get_*_preprocessor` functions
+    pub fn get_preprocessor_with_options(lang: Lang, options: &PreprocessorOptions) -> super::Preprocessor {
+        #[cfg(feature = "ast")]
+        if let Some(ast) = super::ast::Ast::new(lang).filter(|_| options.ast) {
+            return vec![Box::new(ast)];
+        }
+
+        let extra = options.boilerplate;
+        let mut ret = match lang {
+            Lang::Java => java::get_preprocessor_with_boilerplate(extra),
+            Lang::Cpp => cpp::get_preprocessor_with_options(extra, options.drop_directives),
+            Lang::Python | Lang::Notebook => python::get_preprocessor_with_boilerplate(extra),
+            Lang::Text => text::get_preprocessor_with_stop_words(text::stop_words().into_iter().chain(options.stop_words.iter().copied()).collect()),
+            Lang::Generic => generic::get_preprocessor_with_boilerplate(extra),
+        };
+        if !options.canonical {
+            return ret;
         }
+
+        let reorder = match lang {
+            Lang::Java => java::reorder(),
+            Lang::Cpp => cpp::reorder(),
+            Lang::Python | Lang::Notebook => python::reorder(),
+            Lang::Generic => generic::reorder(),
+            Lang::Text => return ret,
+        };
+        // Whitespace removal is the last step, and indentation-based blocks can only be found before it
+        ret.insert(ret.len() - 1, Box::new(reorder));
+        ret
     }
 
     #[cfg(test)]
@@ -104,7 +328,16 @@ pub mod langs {
     pub mod cpp;
     #[cfg(test)]
     pub mod python;
+    #[cfg(test)]
+    pub mod text;
+    #[cfg(test)]
+    pub mod generic;
 }
 
+pub mod notebook;
+pub mod segments;
+#[cfg(feature = "ast")]
+pub mod ast;
+
 #[cfg(test)]
 mod tests;
\ No newline at end of file
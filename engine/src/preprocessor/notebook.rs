@@ -0,0 +1,91 @@
+//! Support for Jupyter notebooks (`.ipynb`).
+//!
+//! A notebook is a JSON document, so it cannot be preprocessed directly.  Instead, the code cells are extracted in order and joined into a
+//! single Python source, which is then run through the Python preprocessor.  Positions in the joined source can be mapped back to a
+//! (cell index, line) pair for reporting.  Markdown/raw cells and cell outputs are ignored.
+
+extern crate serde_json;
+
+use serde_json::Value;
+
+use super::{apply, langs, Seq};
+
+/// The code cells of a notebook, joined into a single source
+pub struct Notebook {
+    source: String,
+    /// (index of the cell in the notebook, char offset where the cell starts in `source`), sorted by offset
+    cells: Vec<(usize, usize)>,
+    /// Char offset where each line starts in `source`, so that `locate` does not need to scan the source
+    line_starts: Vec<usize>,
+    /// Length of `source` in chars
+    len: usize,
+}
+
+impl Notebook {
+    /// Parses a notebook in the nbformat 4 JSON format.
+    ///
+    /// Cells are separated by a newline in the joined source.  IPython-specific lines (`%magic` and `!shell` commands) are blanked out,
+    /// but kept as empty lines so that line numbers within each cell stay correct
+    pub fn parse(contents: &str) -> Result<Self, serde_json::Error> {
+        let json: Value = serde_json::from_str(contents)?;
+        let mut source = String::new();
+        let mut cells = vec![];
+        let mut offset = 0;
+
+        let all_cells = json.get("cells").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+        for (i, cell) in all_cells.iter().enumerate() {
+            if cell.get("cell_type").and_then(Value::as_str) != Some("code") {
+                continue;
+            }
+
+            // `source` is either a single string or a list of lines (which keep their trailing newlines)
+            let cell_source = match cell.get("source") {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+                _ => String::new(),
+            };
+
+            if !cells.is_empty() {
+                source.push('\n');
+                offset += 1;
+            }
+            cells.push((i, offset));
+
+            let cell_source = cell_source.split('\n').map(|line| {
+                match line.trim_start().chars().next() {
+                    Some('%') | Some('!') => "",
+                    _ => line,
+                }
+            }).collect::<Vec<_>>().join("\n");
+            offset += cell_source.chars().count();
+            source.push_str(&cell_source);
+        }
+
+        let line_starts = std::iter::once(0).chain(source.chars().enumerate().filter(|(_, c)| *c == '\n').map(|(i, _)| i + 1)).collect();
+        Ok(Self { len: offset, source, cells, line_starts })
+    }
+
+    /// Returns the joined source of all code cells
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Runs the joined source through the Python preprocessor (see `langs::get_preprocessor_with_options`).  Positions in the result refer to chars of `source()`
+    pub fn preprocess(&self, options: &langs::PreprocessorOptions) -> Seq {
+        apply(&self.source, &langs::get_preprocessor_with_options(langs::Lang::Notebook, options))
+    }
+
+    /// Maps a char position in the joined source back to (cell index, line within the cell).  Both are 0-indexed, and the cell index counts all
+    /// cells in the notebook, including markdown cells.  Returns None if the position is out of bounds
+    pub fn locate(&self, pos: usize) -> Option<(usize, usize)> {
+        if pos >= self.len {
+            return None;
+        }
+
+        let cell = self.cells.partition_point(|(_, start)| *start <= pos) - 1;
+        let (index, start) = self.cells[cell];
+        // Every cell starts on a new line, so the cell's first line is found the same way as the position's line
+        let line_of = |offset: usize| self.line_starts.partition_point(|s| *s <= offset) - 1;
+        Some((index, line_of(pos) - line_of(start)))
+    }
+}
@@ -0,0 +1,303 @@
+//! Lightweight detection of function/method and class boundaries, so that matches can be reported per segment rather than per file.
+//!
+//! This is not a parser: brace languages are segmented by looking at the text before each `{`, and Python by indentation.  It is meant to
+//! be right for typical submissions, and to fail gracefully (by missing a segment) on anything unusual.
+
+use super::langs::Lang;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SegmentKind {
+    Function,
+    Class,
+}
+
+/// A function or class in a document.  `start..end` is the range of chars it spans in the original document, including its header
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Segment {
+    /// Checks if a position in the original document lies inside the segment
+    pub fn contains(&self, pos: usize) -> bool {
+        self.start <= pos && pos < self.end
+    }
+}
+
+/// Finds the functions and classes in a document.  Nested segments (i.e. methods inside a class) are all returned, sorted by start position
+pub fn find_segments(lang: Lang, source: &str) -> Vec<Segment> {
+    let chars = source.chars().collect::<Vec<_>>();
+    let mut ret = match lang {
+        Lang::Cpp => brace_segments(&mask(&chars, true)),
+        Lang::Java | Lang::Generic => brace_segments(&mask(&chars, false)),
+        Lang::Python | Lang::Notebook => indent_segments(&chars),
+        Lang::Text => vec![],
+    };
+
+    ret.sort_by_key(|s| s.start);
+    ret
+}
+
+/// Keywords that can be followed by a parenthesized expression and a block, but do not start a function
+const CONTROL_KEYWORDS: [&str; 10] = ["if", "for", "while", "switch", "catch", "return", "sizeof", "synchronized", "try", "foreach"];
+/// Keywords that start a class-like segment
+const CLASS_KEYWORDS: [&str; 5] = ["class", "struct", "interface", "enum", "union"];
+/// Qualifiers that can come between a function's parameter list and its body.  `throws` starts a Java exception list
+const FUNCTION_QUALIFIERS: [&str; 8] = ["const", "volatile", "noexcept", "override", "final", "mutable", "throw", "throws"];
+
+#[inline(always)]
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// Replaces comments, string/char literals and (optionally) preprocessor directives with spaces, so that braces and parentheses inside them
+/// are ignored.  Positions are unchanged
+fn mask(chars: &[char], directives: bool) -> Vec<char> {
+    let mut ret = chars.to_vec();
+    let mut line_start = true;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let end = match chars[i] {
+            '/' if chars.get(i + 1) == Some(&'/') => chars[i..].iter().position(|c| *c == '\n').map_or(chars.len(), |p| i + p),
+            '/' if chars.get(i + 1) == Some(&'*') => (i + 2..chars.len().saturating_sub(1)).find(|j| chars[*j] == '*' && chars[j + 1] == '/').map_or(chars.len(), |j| j + 2),
+            '#' if directives && line_start => chars[i..].iter().position(|c| *c == '\n').map_or(chars.len(), |p| i + p),
+            q @ ('"' | '\'') => {
+                let mut j = i + 1;
+                while j < chars.len() && chars[j] != q && chars[j] != '\n' {
+                    j += if chars[j] == '\\' { 2 } else { 1 };
+                }
+                (j + 1).min(chars.len())
+            },
+            _ => i + 1,
+        };
+
+        if end > i + 1 {
+            ret[i..end].iter_mut().filter(|c| **c != '\n').for_each(|c| *c = ' ');
+        }
+        line_start = match chars[end - 1] {
+            '\n' => true,
+            c if c.is_whitespace() => line_start,
+            _ => false,
+        };
+        i = end;
+    }
+
+    ret
+}
+
+/// Works out whether the header before a `{` (i.e. `public static int fib(int n)`) starts a segment, and if so which one
+fn classify_header(header: &[char]) -> Option<(SegmentKind, String)> {
+    let header = strip_annotations(header);
+    let words = header.split(|c| !is_ident_char(*c)).filter(|w| !w.is_empty()).map(|w| w.iter().collect::<String>()).collect::<Vec<_>>();
+
+    if let Some(p) = words.iter().position(|w| CLASS_KEYWORDS.contains(&w.as_str())) {
+        return words[p + 1..].iter().find(|w| !CLASS_KEYWORDS.contains(&w.as_str())).map(|name| (SegmentKind::Class, name.clone()));
+    }
+
+    // Functions: the name is right before the first top-level parenthesis, i.e. `Fib(int n) : n(n) {` is `Fib`.  A `{` inside unclosed
+    // parentheses (`sort(a, b, [&](int x) {` or `push({x, y})`) is an argument of a call, not a body
+    let paren = header.iter().position(|c| *c == '(')?;
+    let close = matching_paren(&header, paren)?;
+    if !is_function_tail(&header[close + 1..]) {
+        return None;
+    }
+    let before = header[..paren].iter().collect::<String>();
+    let before = before.trim_end();
+    if before.contains('=') {
+        return None; // lambdas and initializers
+    }
+
+    let name_len = before.chars().rev().take_while(|c| is_ident_char(*c)).count();
+    let name = if name_len > 0 {
+        before.chars().skip(before.chars().count() - name_len).collect::<String>()
+    }
+    else { // operator overloads
+        let op = before.trim_end_matches(|c: char| !is_ident_char(c) && !c.is_whitespace()).trim_end();
+        if !op.ends_with("operator") {
+            return None;
+        }
+        format!("operator{}", before[op.len()..].trim())
+    };
+
+    if name.is_empty() || name.chars().next().unwrap().is_ascii_digit() || CONTROL_KEYWORDS.contains(&name.as_str()) {
+        return None;
+    }
+    Some((SegmentKind::Function, name))
+}
+
+/// Returns the index of the `)` that closes the `(` at `open`, or None if it is never closed
+fn matching_paren(chars: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in chars.iter().enumerate().skip(open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Checks that what follows a function's parameter list can only be qualifiers (`const`, `noexcept(false)`, `&&`), a trailing return type
+/// (`-> T`), a constructor initializer list (`: x(0)`) or a Java `throws` clause, so that headers with anything else are not functions
+fn is_function_tail(tail: &[char]) -> bool {
+    let mut throws = false;
+    let mut i = 0;
+    while i < tail.len() {
+        let c = tail[i];
+        if c.is_whitespace() || c == '&' || (throws && matches!(c, ',' | '.')) {
+            i += 1;
+        }
+        else if (c == '-' && tail.get(i + 1) == Some(&'>')) || (c == ':' && tail.get(i + 1) != Some(&':')) {
+            return true;
+        }
+        else if c == '(' {
+            match matching_paren(tail, i) {
+                Some(close) => i = close + 1,
+                None => return false,
+            }
+        }
+        else if is_ident_char(c) {
+            let len = tail[i..].iter().take_while(|c| is_ident_char(**c)).count();
+            let word = tail[i..i + len].iter().collect::<String>();
+            if !throws && !FUNCTION_QUALIFIERS.contains(&word.as_str()) {
+                return false;
+            }
+            throws |= word == "throws";
+            i += len;
+        }
+        else {
+            return false;
+        }
+    }
+    true
+}
+
+/// Removes Java-style annotations (`@Override`, `@SuppressWarnings("x")`) from a header
+fn strip_annotations(header: &[char]) -> Vec<char> {
+    let mut ret = vec![];
+    let mut i = 0;
+    while i < header.len() {
+        if header[i] != '@' {
+            ret.push(header[i]);
+            i += 1;
+            continue;
+        }
+
+        i += 1;
+        while i < header.len() && (is_ident_char(header[i]) || header[i] == '.') {
+            i += 1;
+        }
+        if header.get(i) == Some(&'(') {
+            let mut depth = 0;
+            while i < header.len() {
+                match header[i] {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {},
+                }
+                i += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+        }
+    }
+
+    ret
+}
+
+/// Segments a (masked) document in a brace language
+fn brace_segments(chars: &[char]) -> Vec<Segment> {
+    let mut ret = vec![];
+    let mut stack: Vec<Option<(SegmentKind, String, usize)>> = vec![];
+    let mut header_start = 0;
+
+    for (i, c) in chars.iter().enumerate() {
+        match c {
+            '{' => {
+                let start = (header_start..i).find(|j| !chars[*j].is_whitespace()).unwrap_or(i);
+                stack.push(classify_header(&chars[start..i]).map(|(kind, name)| (kind, name, start)));
+                header_start = i + 1;
+            },
+            '}' => {
+                if let Some(Some((kind, name, start))) = stack.pop() {
+                    ret.push(Segment { kind, name, start, end: i + 1 });
+                }
+                header_start = i + 1;
+            },
+            ';' => header_start = i + 1,
+            _ => {},
+        }
+    }
+
+    ret
+}
+
+/// Segments a Python document by indentation
+fn indent_segments(chars: &[char]) -> Vec<Segment> {
+    // (start, end, indentation, in_string) of each line, where in_string means that the line starts inside a triple-quoted string
+    let mut lines = vec![];
+    let mut start = 0;
+    let mut in_string = false;
+    for (i, c) in chars.iter().enumerate().chain(std::iter::once((chars.len(), &'\n'))) {
+        if *c == '\n' {
+            let line = chars[start..i].iter().collect::<String>();
+            let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+            lines.push((start, i, indent, in_string));
+            if (line.matches("\"\"\"").count() + line.matches("'''").count()) % 2 == 1 {
+                in_string = !in_string;
+            }
+            start = i + 1;
+        }
+    }
+
+    let mut ret = vec![];
+    for (l, (start, end, indent, in_string)) in lines.iter().enumerate() {
+        let line = chars[*start..*end].iter().collect::<String>();
+        let stmt = line.trim_start();
+        let (kind, rest) = if let Some(rest) = stmt.strip_prefix("class ") {
+            (SegmentKind::Class, rest)
+        }
+        else if let Some(rest) = stmt.strip_prefix("def ").or_else(|| stmt.strip_prefix("async def ")) {
+            (SegmentKind::Function, rest)
+        }
+        else {
+            continue;
+        };
+        if *in_string {
+            continue;
+        }
+
+        let name = rest.trim_start().chars().take_while(|c| is_ident_char(*c)).collect::<String>();
+        if name.is_empty() {
+            continue;
+        }
+
+        // The body is every following line that is blank, inside a string, or indented further than the header
+        let mut seg_end = *end;
+        for (b_start, b_end, b_indent, b_in_string) in &lines[l + 1..] {
+            let blank = chars[*b_start..*b_end].iter().all(|c| c.is_whitespace());
+            if blank {
+                continue;
+            }
+            if !b_in_string && b_indent <= indent {
+                break;
+            }
+            seg_end = *b_end;
+        }
+
+        ret.push(Segment { kind, name, start: start + indent, end: seg_end });
+    }
+
+    ret
+}
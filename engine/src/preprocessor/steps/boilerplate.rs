@@ -0,0 +1,103 @@
+use crate::preprocessor::{Seq, Step, Symbol};
+
+/// A step that removes boilerplate statements, such as imports, package declarations and fast I/O setup.
+/// - Each pattern is matched against the start of a statement, i.e. the start of a line or the text after a `;`, `{` or `}`
+/// - Matching ignores whitespace (`# include` matches `#include`), but cannot cross lines.  If a pattern ends in an identifier
+///   character, the match must also end on a word boundary so that `import` does not match `important`
+/// - A matched statement is removed up to and including the next `;`, or up to the end of the line if there is none.  Lines inside
+///   brackets opened by the statement, or after a trailing `\`, belong to the same statement
+///
+/// This step needs line information, so it should be applied before whitespace is removed
+pub struct Boilerplate {
+    patterns: Vec<Vec<char>>,
+}
+
+impl Boilerplate {
+    pub fn new(patterns: Vec<&str>) -> Self {
+        Self {
+            patterns: patterns.into_iter().map(|p| p.chars().filter(|c| !c.is_whitespace()).collect()).collect(),
+        }
+    }
+
+    /// Returns the index just past the end of the matched pattern, if any pattern matches at index `i`
+    fn match_at(&self, input: &Seq, i: usize) -> Option<usize> {
+        self.patterns.iter().filter(|p| !p.is_empty()).find_map(|pattern| {
+            let mut j = i;
+            for c in pattern {
+                while j < input.len() && input[j].1.is_whitespace() && input[j].1 != '\n' {
+                    j += 1;
+                }
+                if j >= input.len() || input[j].1 != *c {
+                    return None;
+                }
+                j += 1;
+            }
+
+            let word_end = !is_ident_char(*pattern.last().unwrap()) || !input.get(j).and_then(|c| c.1.as_char()).is_some_and(is_ident_char);
+            if word_end { Some(j) } else { None }
+        })
+    }
+}
+
+#[inline(always)]
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[inline(always)]
+fn bracket_delta(s: Symbol) -> isize {
+    match s.as_char() {
+        Some('(' | '[') => 1,
+        Some(')' | ']') => -1,
+        _ => 0,
+    }
+}
+
+impl Step for Boilerplate {
+    fn apply(&self, input: &Seq) -> Seq {
+        let mut ret = vec![];
+        let mut statement_start = true;
+        let mut i = 0;
+
+        while i < input.len() {
+            if statement_start {
+                statement_start = false;
+
+                let mut j = i;
+                while j < input.len() && input[j].1.is_whitespace() && input[j].1 != '\n' {
+                    j += 1;
+                }
+                if let Some(mut end) = self.match_at(input, j) { // Found boilerplate, skip the rest of the statement
+                    if input[end - 1].1 == ';' { // the pattern already covered the whole statement
+                        statement_start = true;
+                    }
+                    else {
+                        // Brackets opened by the statement continue it across lines (i.e. `from x import (\n a,\n b)`), as does a
+                        // trailing `\`
+                        let mut depth = input[j..end].iter().fold(0isize, |d, c| d + bracket_delta(c.1));
+                        while end < input.len() {
+                            let c = input[end].1;
+                            if depth <= 0 && (c == ';' || (c == '\n' && input[end - 1].1 != '\\')) {
+                                break;
+                            }
+                            depth += bracket_delta(c);
+                            end += 1;
+                        }
+                        if end < input.len() && input[end].1 == ';' {
+                            end += 1;
+                            statement_start = true;
+                        }
+                    }
+                    i = end;
+                    continue;
+                }
+            }
+
+            statement_start = matches!(input[i].1.as_char(), Some('\n' | ';' | '{' | '}'));
+            ret.push(input[i]);
+            i += 1;
+        }
+
+        ret
+    }
+}
@@ -0,0 +1,24 @@
+use crate::preprocessor::{Step, Seq};
+
+/// A step that folds all characters to lowercase.  Characters that lowercase into several characters keep the same position, and tokens are
+/// kept as-is
+pub struct Lowercase {}
+
+impl Step for Lowercase {
+    fn apply(&self, input: &Seq) -> Seq {
+        let mut ret = vec![];
+        for (i, s) in input {
+            match s.as_char() {
+                Some(c) => ret.extend(c.to_lowercase().map(|l| (*i, l.into()))),
+                None => ret.push((*i, *s)),
+            }
+        }
+        ret
+    }
+}
+
+impl Lowercase {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
@@ -1,22 +1,40 @@
 use crate::preprocessor::{Step, Seq};
 
+/// A block comment delimited by a start and end token.  If `nested` is set, start tokens inside the comment open a new level of nesting
+struct BlockToken {
+    start: Vec<char>,
+    end: Vec<char>,
+    nested: bool,
+}
+
+/// A step that removes comments.
+/// - Any number of line comment tokens (i.e. `//`, `#`, `--`) and block comment tokens (i.e. `/* */`) can be given
+/// - Block comments are given as `(start, end, nested)`.  Nested block comments such as Rust's `/* /* */ */` only end once every start
+///   token has been closed
+/// - When several tokens match at the same position, the longest one wins, so that i.e. Lua's `--[[` is not read as the line comment `--`
+/// - Line comments run until the end of the line.  The newline itself is kept
 pub struct Comment {
-    line_token: Vec<char>,
-    block_token: (Vec<char>, Vec<char>),
+    line_tokens: Vec<Vec<char>>,
+    block_tokens: Vec<BlockToken>,
 }
 
 impl Comment {
-    pub fn new(line_token: &str, block_token: (&str, &str)) -> Self {
+    pub fn new(line_tokens: Vec<&str>, block_tokens: Vec<(&str, &str, bool)>) -> Self {
         Self {
-            line_token: line_token.chars().collect(),
-            block_token: (block_token.0.chars().collect(), block_token.1.chars().collect()),
+            line_tokens: line_tokens.into_iter().map(|t| t.chars().collect()).collect(),
+            block_tokens: block_tokens.into_iter().map(|(start, end, nested)| BlockToken {
+                start: start.chars().collect(),
+                end: end.chars().collect(),
+                nested,
+            }).collect(),
         }
     }
 }
 
 /// shorthand substring function
-fn check_for_str(haystack: &Seq, index: usize, needle: &Vec<char>) -> bool {
-    index + needle.len() <= haystack.len() && // have enough space!!
+fn check_for_str(haystack: &Seq, index: usize, needle: &[char]) -> bool {
+    !needle.is_empty() && // empty tokens never match
+        index + needle.len() <= haystack.len() && // have enough space!!
         haystack[index..index + needle.len()].iter().enumerate().all(|(j, (_, c))| *c == needle[j]) // compare chars
 }
 
@@ -26,34 +44,40 @@ impl Step for Comment {
         let mut i = 0;
 
         while i < input.len() {
-            if check_for_str(input, i + 1, &self.line_token) { // Found a line comment
-                ret.push(input[i]);
-
-                // TODO: CRLF
-                while i < input.len() && input[i].1 != '\n' {
-                    i += 1;
-                }
-                if i < input.len() && input[i].1 == '\n' { // skip the newline
-                    i += 1;
-                }
-            }
-            else if check_for_str(input, i + 1, &self.block_token.0) { 
-                ret.push(input[i]);
-
-                i += 1 + self.block_token.0.len();
-                while i < input.len() && !check_for_str(input, i, &self.block_token.1) {
-                    i += 1;
-                }
-                if check_for_str(input, i, &self.block_token.1) { // skip the ending block
-                    i += self.block_token.1.len();
-                }
-            }
-            else { // not a comment
-                ret.push(input[i]);
-                i += 1
+            let line = self.line_tokens.iter().filter(|t| check_for_str(input, i, t)).map(|t| t.len()).max();
+            let block = self.block_tokens.iter().filter(|b| check_for_str(input, i, &b.start)).max_by_key(|b| b.start.len());
+
+            match (line, block) {
+                (Some(line_len), block) if block.is_none_or(|b| b.start.len() < line_len) => { // Found a line comment
+                    // TODO: CRLF
+                    while i < input.len() && input[i].1 != '\n' {
+                        i += 1;
+                    }
+                },
+                (_, Some(block)) => { // Found a block comment
+                    let mut depth = 1;
+                    i += block.start.len();
+                    while i < input.len() && depth > 0 {
+                        if check_for_str(input, i, &block.end) {
+                            depth -= 1;
+                            i += block.end.len();
+                        }
+                        else if block.nested && check_for_str(input, i, &block.start) {
+                            depth += 1;
+                            i += block.start.len();
+                        }
+                        else {
+                            i += 1;
+                        }
+                    }
+                },
+                _ => { // not a comment
+                    ret.push(input[i]);
+                    i += 1
+                },
             }
         }
 
         ret
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,38 @@
-use crate::preprocessor::{Seq, Step};
+use crate::preprocessor::{text_of, Seq, Step, Symbol};
 use std::collections::HashMap;
 
-pub(crate) const KEYWORD_OFF: usize = '\u{E000}' as usize; // Smallest private use area unicode character
+/// The set of characters that make up an identifier in a language.
+/// - `start`: characters that can begin an identifier
+/// - `rest`: characters that can continue an identifier
+/// - `end`: characters that can only appear as the last character of an identifier (i.e. Ruby's `empty?` and `sort!`)
+///
+/// For example, PHP variables can be matched with `start` accepting `$`, and Lisp's `kebab-case-names` with `rest` accepting `-`
+#[derive(Clone, Copy)]
+pub struct IdentifierGrammar {
+    start: fn(char) -> bool,
+    rest: fn(char) -> bool,
+    end: fn(char) -> bool,
+}
+
+impl IdentifierGrammar {
+    /// C-style identifiers: a letter or underscore followed by letters, digits and underscores.  Used for C/C++ and Python
+    pub const C_LIKE: IdentifierGrammar = IdentifierGrammar::new(
+        |c| c.is_alphabetic() || c == '_',
+        |c| c.is_alphanumeric() || c == '_',
+        |_| false,
+    );
+
+    /// Java/JavaScript identifiers, which also allow `$` anywhere
+    pub const JAVA: IdentifierGrammar = IdentifierGrammar::new(
+        |c| c.is_alphabetic() || c == '_' || c == '$',
+        |c| c.is_alphanumeric() || c == '_' || c == '$',
+        |_| false,
+    );
+
+    pub const fn new(start: fn(char) -> bool, rest: fn(char) -> bool, end: fn(char) -> bool) -> Self {
+        Self { start, rest, end }
+    }
+}
 
 // DFA for matching identifiers
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -9,6 +40,7 @@ enum DFA {
     // DFA states
     First, // Initial state
     SecondPlus, // Intermediate state
+    Last, // Read a character that can only end an identifier
     Invalid, // Match failed, definitely not identifier
     Done, // Match is complete, full word matched
 }
@@ -21,17 +53,18 @@ impl DFA {
     /**
      * None will behave as if the character is invalid (i.e. punctuation)
      */
-    pub fn advance(&self, c_opt: Option<char>) -> DFA {
+    pub fn advance(&self, c_opt: Option<char>, grammar: &IdentifierGrammar) -> DFA {
         match c_opt {
             None => match *self {
                 Self::First | Self::Invalid => Self::Invalid,
                 _ => Self::Done
             }
             Some(c) => match self {
-                Self::First if c.is_alphabetic() || c == '_' => Self::SecondPlus,
+                Self::First if (grammar.start)(c) => Self::SecondPlus,
                 Self::First => Self::Invalid,
-                Self::SecondPlus if c.is_alphanumeric() || c == '_' => Self::SecondPlus,
-                Self::SecondPlus => Self::Done,
+                Self::SecondPlus if (grammar.rest)(c) => Self::SecondPlus,
+                Self::SecondPlus if (grammar.end)(c) => Self::Last,
+                Self::SecondPlus | Self::Last => Self::Done,
                 o => *o
             }
         }
@@ -42,51 +75,83 @@ impl DFA {
     }
 }
 
+/// Returns the length of the numeric literal starting at index `i`, or 0 if there is none.
+/// Numbers are read greedily so that literals like `1e5`, `1e-5`, `0x1F`, `1'000'000` and `10ll` stay whole instead of having their
+/// letters read as an identifier
+fn number_len(input: &Seq, i: usize) -> usize {
+    if !input[i].1.is_ascii_digit() {
+        return 0;
+    }
+
+    let hex = input[i].1 == '0' && input.get(i + 1).is_some_and(|c| c.1 == 'x' || c.1 == 'X');
+    let mut j = i + 1;
+    while j < input.len() {
+        let (Some(prev), Some(c)) = (input[j - 1].1.as_char(), input[j].1.as_char()) else { break };
+        let exponent_sign = (c == '+' || c == '-') && if hex { prev == 'p' || prev == 'P' } else { prev == 'e' || prev == 'E' };
+        let separator = c == '\'' && prev.is_ascii_hexdigit() && input.get(j + 1).is_some_and(|c| c.1.is_ascii_hexdigit());
+
+        if c.is_alphanumeric() || c == '_' || c == '.' || exponent_sign || separator {
+            j += 1;
+        }
+        else {
+            break;
+        }
+    }
+
+    j - i
+}
+
 pub struct Identifiers<'a> {
-    keywords: HashMap<&'a str, char>,
-    normalize_into: char,
+    keywords: HashMap<&'a str, Symbol>,
+    grammar: IdentifierGrammar,
+    normalize_into: Symbol,
 }
 
 impl Step for Identifiers<'_> {
     /**
      * A step that normalizes identifiers into a single token.
-     * - An identifier is a string consisting of alphanumeric characters and underscores, where the first character cannot be a digit
+     * - What counts as an identifier is determined by the `IdentifierGrammar`.  Numeric literals are never treated as identifiers, and are kept as-is
      * - The matcher is greedy: it will expand the current match until it can do so no longer
-     * - A list of keywords can be supplied, which are matches that the matcher will ignore.  They should also be valid identifiers
+     * - A list of keywords can be supplied, which are matches that the matcher will ignore.  They should also be valid identifiers.  Each
+     *   keyword is replaced by its own token (see `Symbol::token`), so there is no limit on how many a language can have
      */
     fn apply(&self, input: &Seq) -> Seq {
         let mut i = 0;
         let mut ret = vec![];
 
         while i < input.len() {
+            let num_len = number_len(input, i);
+            if num_len > 0 {
+                ret.extend_from_slice(&input[i..i + num_len]);
+                i += num_len;
+                continue;
+            }
+
+            // After the loop, j is the index of the first char that is not part of the match
             let mut dfa = DFA::new();
             let mut j = i;
-            while !dfa.is_done() {
-                dfa = dfa.advance(match input.get(j) {
-                    Some(c) => Some(c.1),
-                    None => None
-                });
-                j += 1
+            loop {
+                dfa = dfa.advance(input.get(j).and_then(|c| c.1.as_char()), &self.grammar);
+                if dfa.is_done() {
+                    break;
+                }
+                j += 1;
             }
 
             if dfa == DFA::Invalid {
                 ret.push(input[i]);
+                i += 1;
             }
             else {
-                if j < input.len() { // we read past the char in the token if we don't hit EOF, so we back up by 1 if we do
-                    j -= 1; 
-                }
-
-                let matched_str = &input[i..j].iter().map(|c| c.1).collect::<String>();
-                if let Some(repl_chr) = self.keywords.get(matched_str.as_str()) { // oops, keyword
-                    ret.push((input[i].0, *repl_chr));
+                let matched_str = text_of(&input[i..j]).unwrap(); // the grammar only accepts chars
+                if let Some(keyword) = self.keywords.get(matched_str.as_str()) { // oops, keyword
+                    ret.push((input[i].0, *keyword));
                 }
                 else {
                     ret.push((input[i].0, self.normalize_into));
                 }
+                i = j;
             }
-
-            i = j;
         }
 
         ret
@@ -94,10 +159,11 @@ impl Step for Identifiers<'_> {
 }
 
 impl<'a> Identifiers<'a> {
-    pub fn new(keywords_vec: Vec<&'a str>, normalize_into: char) -> Self {
+    pub fn new(keywords_vec: Vec<&'a str>, grammar: IdentifierGrammar, normalize_into: char) -> Self {
         Self {
-            keywords: HashMap::from_iter(keywords_vec.into_iter().enumerate().map(|(i, kw)| (kw, char::from_u32((KEYWORD_OFF + i) as u32).unwrap()))),
-            normalize_into
+            keywords: keywords_vec.into_iter().map(|kw| (kw, Symbol::token(kw))).collect(),
+            grammar,
+            normalize_into: normalize_into.into(),
         }
     }
-}
\ No newline at end of file
+}
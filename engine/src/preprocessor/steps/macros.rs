@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+
+use crate::preprocessor::{Seq, Step};
+
+/// Upper bound on nested macro expansions, guards against pathological (e.g. mutually recursive) definitions
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// Upper bound on the characters produced (summed over all nesting levels) while expanding a single invocation, guards against definitions
+/// that grow exponentially (`#define A B B`, `#define B C C`, ...).  Invocations that exceed it are left unexpanded
+const MAX_EXPANSION_LEN: usize = 1 << 16;
+
+/// A macro definition.  Object-like macros have no parameter list, function-like macros have a (possibly empty) one
+struct Macro {
+    params: Option<Vec<String>>,
+    body: Vec<char>,
+}
+
+/// A step that expands C/C++ preprocessor macros defined in the same file.
+/// - Object-like (`#define ll long long`) and function-like (`#define FOR(i,a,b) for(int i=a;i<b;i++)`) macros are supported,
+///   including `#`, `##` and `__VA_ARGS__`.  `#undef` is respected
+/// - Simple type aliases (`typedef long long ll;` and `using ll = long long;`) are treated as object-like macros.  Alias templates
+///   (`template<class T> using V = vector<T>;`) are left as-is
+/// - Definitions are removed from the output, and every character of an expansion is mapped to the position of the macro name
+/// - If `drop_directives` is set, `#include` and `#pragma` lines are removed as well.  All other directives are left as-is
+///
+/// This step needs line information, so it should be applied before whitespace is removed
+pub struct Macros {
+    drop_directives: bool,
+}
+
+impl Macros {
+    pub fn new(drop_directives: bool) -> Self {
+        Self { drop_directives }
+    }
+
+    /// Handles the contents of a directive line (everything after the `#`).  Returns whether the line should be kept
+    fn directive(&self, text: &[char], macros: &mut HashMap<String, Macro>) -> bool {
+        let text = text.iter().collect::<String>();
+        let rest = text.trim_start();
+        let word_len = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+        let (word, rest) = rest.split_at(word_len);
+
+        match word {
+            "define" => match parse_define(rest) {
+                Some((name, m)) => {
+                    macros.insert(name, m);
+                    false
+                },
+                None => true,
+            },
+            "undef" => {
+                macros.remove(rest.trim());
+                false
+            },
+            "include" | "pragma" => !self.drop_directives,
+            _ => true,
+        }
+    }
+}
+
+#[inline(always)]
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+#[inline(always)]
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Length of the identifier starting at `i`, or 0 if there is none
+fn ident_len(chars: &[char], i: usize) -> usize {
+    if !chars.get(i).is_some_and(|c| is_ident_start(*c)) {
+        return 0;
+    }
+    chars[i..].iter().take_while(|c| is_ident_char(**c)).count()
+}
+
+/// Length of the string or character literal starting at `i`.  Literals are cut off at the end of the line
+fn literal_len(chars: &[char], i: usize) -> usize {
+    let quote = chars[i];
+    let mut j = i + 1;
+    while j < chars.len() && chars[j] != quote && chars[j] != '\n' {
+        if chars[j] == '\\' {
+            j += 1;
+        }
+        j += 1;
+    }
+    if j < chars.len() && chars[j] == quote { j + 1 - i } else { j.min(chars.len()) - i }
+}
+
+/// Length of the number starting at `i`, consumed as one token so that suffixes like `1e5` or `10ll` are not read as identifiers
+fn number_len(chars: &[char], i: usize) -> usize {
+    chars[i..].iter().take_while(|c| is_ident_char(**c) || **c == '.').count()
+}
+
+fn trim(s: &[char]) -> Vec<char> {
+    let start = s.iter().position(|c| !c.is_whitespace()).unwrap_or(s.len());
+    let end = s.iter().rposition(|c| !c.is_whitespace()).map_or(start, |e| e + 1);
+    s[start..end].to_vec()
+}
+
+/// Parses the rest of a `#define` line into a macro name and definition
+fn parse_define(rest: &str) -> Option<(String, Macro)> {
+    let chars = rest.trim_start().chars().collect::<Vec<_>>();
+    let name_len = ident_len(&chars, 0);
+    if name_len == 0 {
+        return None;
+    }
+    let name = chars[..name_len].iter().collect::<String>();
+
+    // Function-like macros need the parenthesis immediately after the name
+    if chars.get(name_len) == Some(&'(') {
+        let close = name_len + chars[name_len..].iter().position(|c| *c == ')')?;
+        let params_str = chars[name_len + 1..close].iter().collect::<String>();
+        let params = if params_str.trim().is_empty() {
+            vec![]
+        } else {
+            params_str.split(',').map(|p| match p.trim() {
+                "..." => String::from("__VA_ARGS__"),
+                p => p.to_string(),
+            }).collect()
+        };
+
+        Some((name, Macro { params: Some(params), body: trim(&chars[close + 1..]) }))
+    }
+    else {
+        Some((name, Macro { params: None, body: trim(&chars[name_len..]) }))
+    }
+}
+
+/// Parses the arguments of a function-like macro invocation.  `i` should point at the opening parenthesis, possibly preceded by whitespace.
+/// Returns the (trimmed) arguments and the index just after the closing parenthesis
+fn parse_args(chars: &[char], mut i: usize) -> Option<(Vec<Vec<char>>, usize)> {
+    while i < chars.len() && chars[i].is_whitespace() {
+        i += 1;
+    }
+    if chars.get(i) != Some(&'(') {
+        return None;
+    }
+
+    let mut args = vec![vec![]];
+    let mut depth = 0;
+    i += 1;
+    while i < chars.len() {
+        match chars[i] {
+            '"' | '\'' => {
+                let len = literal_len(chars, i);
+                args.last_mut().unwrap().extend_from_slice(&chars[i..i + len]);
+                i += len;
+                continue;
+            },
+            ')' if depth == 0 => return Some((args.iter().map(|a| trim(a)).collect(), i + 1)),
+            ',' if depth == 0 => args.push(vec![]),
+            c => {
+                if c == '(' { depth += 1; }
+                if c == ')' { depth -= 1; }
+                args.last_mut().unwrap().push(c);
+            },
+        }
+        i += 1;
+    }
+
+    None
+}
+
+/// Matches invocation arguments to parameters, folding any extra arguments into `__VA_ARGS__`
+fn bind_args(params: &[String], mut args: Vec<Vec<char>>) -> Option<Vec<Vec<char>>> {
+    if params.is_empty() {
+        return if args.len() == 1 && args[0].is_empty() { Some(vec![]) } else { None };
+    }
+
+    if params.last().is_some_and(|p| p == "__VA_ARGS__") && args.len() >= params.len() {
+        let rest = args.split_off(params.len() - 1);
+        args.push(rest.join(&','));
+    }
+    else if params.last().is_some_and(|p| p == "__VA_ARGS__") && args.len() + 1 == params.len() {
+        args.push(vec![]);
+    }
+
+    if args.len() == params.len() { Some(args) } else { None }
+}
+
+/// Builds the body of a function-like macro with its parameters replaced by the corresponding arguments.
+/// Operands of `#` and `##` use the raw argument, all other parameters use the fully expanded argument
+fn substitute(params: &[String], body: &[char], raw_args: &[Vec<char>], expanded_args: &[Vec<char>]) -> Vec<char> {
+    let next_is_paste = |mut i: usize| {
+        while i < body.len() && body[i].is_whitespace() {
+            i += 1;
+        }
+        i + 1 < body.len() && body[i] == '#' && body[i + 1] == '#'
+    };
+
+    let mut ret: Vec<char> = vec![];
+    let mut pasting = false;
+    let mut i = 0;
+    while i < body.len() {
+        let c = body[i];
+        if c == '#' && body.get(i + 1) == Some(&'#') { // token pasting: join the neighbouring tokens
+            while ret.last().is_some_and(|c| c.is_whitespace()) {
+                ret.pop();
+            }
+            i += 2;
+            while i < body.len() && body[i].is_whitespace() {
+                i += 1;
+            }
+            pasting = true;
+            continue;
+        }
+
+        if c == '#' { // stringification
+            let mut j = i + 1;
+            while j < body.len() && body[j].is_whitespace() {
+                j += 1;
+            }
+            let len = ident_len(body, j);
+            if let Some(p) = params.iter().position(|p| body[j..j + len].iter().copied().eq(p.chars())).filter(|_| len > 0) {
+                ret.push('"');
+                ret.extend(raw_args[p].iter().flat_map(|c| match c {
+                    '"' | '\\' => vec!['\\', *c],
+                    c => vec![*c],
+                }));
+                ret.push('"');
+                i = j + len;
+                continue;
+            }
+        }
+
+        let len = ident_len(body, i);
+        if len > 0 {
+            let name = &body[i..i + len];
+            match params.iter().position(|p| name.iter().copied().eq(p.chars())) {
+                Some(p) if pasting || next_is_paste(i + len) => ret.extend_from_slice(&raw_args[p]),
+                Some(p) => ret.extend_from_slice(&expanded_args[p]),
+                None => ret.extend_from_slice(name),
+            }
+            i += len;
+        }
+        else if c == '"' || c == '\'' {
+            let len = literal_len(body, i);
+            ret.extend_from_slice(&body[i..i + len]);
+            i += len;
+        }
+        else {
+            ret.push(c);
+            i += 1;
+        }
+        pasting = false;
+    }
+
+    ret
+}
+
+/// Recognizes `typedef <type> <name>;` and `using <name> = <type>;` starting at `i`.  Returns the alias, its type, and the index
+/// just after the semicolon.  Anything involving braces, parentheses or brackets (structs, function pointers, arrays) is skipped
+fn parse_alias(chars: &[char], i: usize, keyword: &str) -> Option<(String, Vec<char>, usize)> {
+    let end = i + chars[i..].iter().position(|c| *c == ';')?;
+    let stmt = &chars[i + keyword.len()..end];
+    if stmt.iter().any(|c| matches!(c, '{' | '}' | '(' | ')' | '[' | ']' | '"' | '\'' | '#')) {
+        return None;
+    }
+
+    if keyword == "using" {
+        if follows_template(chars, i) {
+            return None;
+        }
+        let eq = stmt.iter().position(|c| *c == '=')?;
+        let name = trim(&stmt[..eq]);
+        let ty = trim(&stmt[eq + 1..]);
+        (ident_len(&name, 0) == name.len() && !name.is_empty() && !ty.is_empty())
+            .then(|| (name.iter().collect(), ty, end + 1))
+    }
+    else {
+        let stmt = trim(stmt);
+        let name_start = stmt.iter().rposition(|c| !is_ident_char(*c)).map_or(0, |p| p + 1);
+        let ty = trim(&stmt[..name_start]);
+        (ident_len(&stmt, name_start) > 0 && !ty.is_empty())
+            .then(|| (stmt[name_start..].iter().collect(), ty, end + 1))
+    }
+}
+
+/// Whether the `using` at `i` is preceded by a `template<...>` header
+fn follows_template(chars: &[char], i: usize) -> bool {
+    let mut j = i;
+    while j > 0 && chars[j - 1].is_whitespace() {
+        j -= 1;
+    }
+    if j == 0 || chars[j - 1] != '>' {
+        return false;
+    }
+
+    let mut depth = 0;
+    while j > 0 {
+        j -= 1;
+        match chars[j] {
+            '>' => depth += 1,
+            '<' => depth -= 1,
+            _ => {},
+        }
+        if depth == 0 {
+            break;
+        }
+    }
+    while j > 0 && chars[j - 1].is_whitespace() {
+        j -= 1;
+    }
+    j >= 8 && chars[j - 8..j].iter().copied().eq("template".chars()) && (j == 8 || !is_ident_char(chars[j - 9]))
+}
+
+/// Fully expands all macro invocations in `chars`.  Macros in `disabled` are currently being expanded and are left alone.
+/// `budget` is the number of characters the expansion may still produce; returns None once it runs out
+fn expand(chars: &[char], macros: &HashMap<String, Macro>, disabled: &mut Vec<String>, budget: &mut usize) -> Option<Vec<char>> {
+    if disabled.len() > MAX_EXPANSION_DEPTH {
+        return Some(chars.to_vec());
+    }
+
+    let mut ret = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let produced = ret.len();
+        let c = chars[i];
+        let len = if c == '"' || c == '\'' { literal_len(chars, i) } else if c.is_ascii_digit() { number_len(chars, i) } else { ident_len(chars, i) };
+        if len == 0 {
+            ret.push(c);
+            i += 1;
+        }
+        else {
+            let name = chars[i..i + len].iter().collect::<String>();
+            match expand_invocation(&name, chars, i + len, macros, disabled, budget) {
+                Some((expansion, end)) => {
+                    ret.extend(expansion);
+                    i = end;
+                },
+                None => {
+                    ret.extend_from_slice(&chars[i..i + len]);
+                    i += len;
+                },
+            }
+        }
+
+        match budget.checked_sub(ret.len() - produced) {
+            Some(rest) => *budget = rest,
+            None => {
+                *budget = 0;
+                return None;
+            },
+        }
+    }
+
+    Some(ret)
+}
+
+/// Expands a single use of the macro `name`, whose arguments (if any) start at `after`.  Returns the expansion and the index just past the
+/// invocation, or None if `name` is not an expandable macro here or its expansion exceeds `budget`
+fn expand_invocation(name: &str, chars: &[char], after: usize, macros: &HashMap<String, Macro>, disabled: &mut Vec<String>, budget: &mut usize) -> Option<(Vec<char>, usize)> {
+    let m = macros.get(name).filter(|_| !disabled.iter().any(|d| d == name))?;
+    let (body, end) = match &m.params {
+        None => (m.body.clone(), after),
+        Some(params) => {
+            let (args, end) = parse_args(chars, after)?;
+            let args = bind_args(params, args)?;
+            let expanded_args = args.iter().map(|a| expand(a, macros, disabled, budget)).collect::<Option<Vec<_>>>()?;
+            (substitute(params, &m.body, &args, &expanded_args), end)
+        },
+    };
+
+    disabled.push(name.to_string());
+    let ret = expand(&body, macros, disabled, budget);
+    disabled.pop();
+
+    Some((ret?, end))
+}
+
+impl Step for Macros {
+    fn apply(&self, input: &Seq) -> Seq {
+        // Tokens can't be part of a macro, so they are treated as an opaque char (they are still copied through from `input`)
+        let chars = input.iter().map(|c| c.1.as_char().unwrap_or(char::REPLACEMENT_CHARACTER)).collect::<Vec<_>>();
+        let mut macros = HashMap::new();
+        let mut ret = vec![];
+        let mut line_start = true;
+        let mut i = 0;
+
+        while i < chars.len() {
+            if line_start {
+                line_start = false;
+                let mut j = i;
+                while j < chars.len() && (chars[j] == ' ' || chars[j] == '\t') {
+                    j += 1;
+                }
+
+                if j < chars.len() && chars[j] == '#' { // Found a directive, which runs until an unescaped newline
+                    let mut end = j;
+                    let mut text = vec![];
+                    while end < chars.len() && chars[end] != '\n' {
+                        if chars[end] == '\\' && chars.get(end + 1) == Some(&'\n') {
+                            end += 2;
+                            continue;
+                        }
+                        text.push(chars[end]);
+                        end += 1;
+                    }
+
+                    if self.directive(&text[1..], &mut macros) {
+                        ret.extend_from_slice(&input[i..end]);
+                    }
+                    i = end; // the newline itself is kept so that the surrounding lines stay separated
+                    continue;
+                }
+            }
+
+            let c = chars[i];
+            let len = if c == '"' || c == '\'' { literal_len(&chars, i) } else if c.is_ascii_digit() { number_len(&chars, i) } else { ident_len(&chars, i) };
+            if len == 0 {
+                line_start = c == '\n';
+                ret.push(input[i]);
+                i += 1;
+                continue;
+            }
+
+            let name = chars[i..i + len].iter().collect::<String>();
+            if name == "typedef" || name == "using" {
+                if let Some((alias, ty, end)) = parse_alias(&chars, i, &name) {
+                    macros.insert(alias, Macro { params: None, body: ty });
+                    i = end;
+                    continue;
+                }
+            }
+
+            let mut budget = MAX_EXPANSION_LEN;
+            match expand_invocation(&name, &chars, i + len, &macros, &mut vec![], &mut budget) {
+                Some((expansion, end)) => {
+                    ret.extend(expansion.into_iter().map(|c| (input[i].0, c.into())));
+                    i = end;
+                },
+                None => {
+                    ret.extend_from_slice(&input[i..i + len]);
+                    i += len;
+                },
+            }
+        }
+
+        ret
+    }
+}
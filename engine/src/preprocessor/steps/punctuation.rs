@@ -0,0 +1,19 @@
+use crate::preprocessor::{Step, Seq};
+
+/// A step that removes punctuation, i.e. every character that is neither alphanumeric nor whitespace.
+/// Words joined by punctuation are merged, so `don't` becomes `dont`
+pub struct Punctuation {}
+
+impl Step for Punctuation {
+    fn apply(&self, input: &Seq) -> Seq {
+        input.iter().filter(|c| c.1.is_alphanumeric() || c.1.is_whitespace())
+            .copied()
+            .collect::<Seq>()
+    }
+}
+
+impl Punctuation {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
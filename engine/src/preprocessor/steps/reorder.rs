@@ -0,0 +1,151 @@
+use crate::preprocessor::{Step, Seq, Symbol};
+
+/// How a language delimits its definitions
+enum Blocks {
+    /// Definitions end with a `;` or a closing `}`, i.e. C++ and Java.  `levels` is how many levels of nesting are sorted: 1 only sorts
+    /// top-level definitions, and 2 also sorts the members of each top-level block (i.e. the methods of a Java class)
+    Braces { levels: usize },
+    /// Statements are lines at indentation 0 along with the indented lines after them, i.e. Python.  Lines at indentation 0 that start with
+    /// one of the `continuations` (i.e. `else`, `except`) belong to the statement before them.  Only statements that start with one of the
+    /// `definitions` (i.e. `def`, `import`, or `@` for decorators) are sorted; the rest are script code whose order matters, so they are kept
+    /// in order after the sorted definitions
+    Indentation { continuations: Vec<Symbol>, definitions: Vec<Symbol> },
+}
+
+/// A step that sorts definitions into a deterministic order, so that reordering functions, declarations or imports does not affect matching.
+///
+/// Definitions are sorted by their contents with whitespace ignored, so this should run after identifiers and keywords are normalized (and for
+/// indentation-based languages, before whitespace is removed).  Each symbol keeps its original position, so matches found in the reordered
+/// sequence can still be mapped back to spans of the original document
+pub struct Reorder {
+    blocks: Blocks,
+}
+
+impl Reorder {
+    pub fn braces(levels: usize) -> Self {
+        Self { blocks: Blocks::Braces { levels } }
+    }
+
+    pub fn indentation(continuations: Vec<Symbol>, definitions: Vec<Symbol>) -> Self {
+        Self { blocks: Blocks::Indentation { continuations, definitions } }
+    }
+}
+
+/// Sorts chunks by their contents (ignoring whitespace) and joins them back together.  The sort is stable, so identical chunks keep their order
+fn sort_chunks(mut chunks: Vec<Seq>) -> Seq {
+    chunks.sort_by_cached_key(|chunk| chunk.iter().map(|c| c.1).filter(|c| !c.is_whitespace()).collect::<Vec<_>>());
+    chunks.concat()
+}
+
+/// Splits a sequence into brace-delimited definitions and sorts them, recursing into the body of each definition while `levels` > 1
+fn sort_braces(input: &[(usize, Symbol)], levels: usize) -> Seq {
+    if levels == 0 {
+        return input.to_vec();
+    }
+
+    let mut chunks = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut body_start = None; // index of the `{` that opens the current chunk's body
+    let mut i = 0;
+
+    while i < input.len() {
+        match input[i].1.as_char() {
+            Some('(' | '[') => depth += 1,
+            Some(')' | ']') => depth = depth.saturating_sub(1),
+            Some('{') => {
+                if depth == 0 && body_start.is_none() {
+                    body_start = Some(i);
+                }
+                depth += 1;
+            },
+            Some('}') => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    // `};` (i.e. after a C++ class) belongs to the same definition
+                    let mut end = i + 1;
+                    if let Some(semi) = (end..input.len()).find(|j| !input[*j].1.is_whitespace()).filter(|j| input[*j].1 == ';') {
+                        end = semi + 1;
+                    }
+
+                    chunks.push(match body_start {
+                        Some(open) => [&input[start..=open], &sort_braces(&input[open + 1..i], levels - 1), &input[i..end]].concat(),
+                        None => input[start..end].to_vec(), // unbalanced `}`
+                    });
+
+                    start = end;
+                    body_start = None;
+                    i = end;
+                    continue;
+                }
+            },
+            Some(';') if depth == 0 => {
+                chunks.push(input[start..=i].to_vec());
+                start = i + 1;
+                body_start = None;
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+
+    if start < input.len() {
+        chunks.push(input[start..].to_vec());
+    }
+
+    sort_chunks(chunks)
+}
+
+/// Splits a sequence into indentation-delimited statements, and sorts the definitions among them
+fn sort_indentation(input: &[(usize, Symbol)], continuations: &[Symbol], definitions: &[Symbol]) -> Seq {
+    let mut chunks: Vec<Seq> = vec![];
+    let mut depth = 0usize; // bracket depth, since a line inside brackets continues the previous one
+    let mut continued = false; // whether the next line continues the current chunk (after a decorator or a trailing backslash)
+    let mut line_start = 0;
+
+    for i in 0..input.len() {
+        if input[i].1 != '\n' && i + 1 != input.len() {
+            match input[i].1.as_char() {
+                Some('(' | '[' | '{') => depth += 1,
+                Some(')' | ']' | '}') => depth = depth.saturating_sub(1),
+                _ => {},
+            }
+            continue;
+        }
+
+        let line = &input[line_start..=i];
+        let first = line.iter().map(|c| c.1).find(|c| !c.is_whitespace());
+        let new_chunk = !continued && first.is_some() && !line[0].1.is_whitespace() && !continuations.contains(&line[0].1);
+
+        match chunks.last_mut() {
+            Some(chunk) if !new_chunk => chunk.extend_from_slice(line),
+            _ => chunks.push(line.to_vec()),
+        }
+
+        let last = line.iter().map(|c| c.1).rfind(|c| !c.is_whitespace());
+        if let Some(c) = first { // blank lines do not change whether the chunk continues
+            continued = depth > 0 || c == '@' || last.is_some_and(|c| c == '\\');
+        }
+        line_start = i + 1;
+    }
+
+    // Every chunk must end with a newline, or the last line would be joined with the next chunk
+    for chunk in chunks.iter_mut() {
+        if chunk.last().is_some_and(|c| c.1 != '\n') {
+            let pos = chunk.last().unwrap().0;
+            chunk.push((pos, '\n'.into()));
+        }
+    }
+
+    let (defs, script): (Vec<_>, Vec<_>) = chunks.into_iter().partition(|chunk| definitions.contains(&chunk[0].1));
+    [sort_chunks(defs), script.concat()].concat()
+}
+
+impl Step for Reorder {
+    fn apply(&self, input: &Seq) -> Seq {
+        match &self.blocks {
+            Blocks::Braces { levels } => sort_braces(input, *levels),
+            Blocks::Indentation { continuations, definitions } => sort_indentation(input, continuations, definitions),
+        }
+    }
+}
@@ -1,12 +1,12 @@
 use std::collections::HashMap;
 
-use super::super::{Step, Seq};
+use super::super::{text_of, Step, Seq, Symbol};
 
 /// A step that replaces substrings with characters, using a rolling hash algorithm to minimize runtime.
 /// The mappings are specified as a vector of tuples (s1 => c1), ..., (sk => ck).  For optimization reasons,
 /// the source strings s1, ..., sk must all be the same length.
 pub struct SameSizeReplace<'a> {
-    mappings: HashMap<&'a str, char>,
+    mappings: HashMap<&'a str, Symbol>,
     len: usize,
 }
 
@@ -17,7 +17,7 @@ impl<'a> SameSizeReplace<'a> {
 
         Self {
             len: mappings[0].0.len(),
-            mappings: mappings.into_iter().collect(),
+            mappings: mappings.into_iter().map(|(s, c)| (s, c.into())).collect(),
         }
     }
 }
@@ -33,7 +33,7 @@ impl<'a> Step for SameSizeReplace<'a> {
 
             while i < input.len() {
                 if i + self.len <= input.len() {
-                    let matched_str = &input[i..i+self.len].iter().map(|c| c.1).collect::<String>();
+                    let matched_str = text_of(&input[i..i+self.len]).unwrap_or_default(); // tokens never match
                     if let Some(c) = self.mappings.get(matched_str.as_str()) {
                         ret.push((i, *c));
                         i += self.len;
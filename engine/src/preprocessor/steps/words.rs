@@ -0,0 +1,43 @@
+use std::collections::HashSet;
+
+use crate::preprocessor::{text_of, Step, Seq, Symbol};
+
+/// A step that collapses each word into a single token, for matching natural-language text word by word.
+/// - A word is a maximal run of non-whitespace characters.  Whitespace is removed
+/// - Words in the stop word list (i.e. "the", "and") are removed entirely
+/// - Each word is mapped to a single token using `Symbol::token`, so the same word always maps to the same token across documents
+pub struct Words {
+    stop_words: HashSet<String>,
+}
+
+impl Words {
+    pub fn new(stop_words: Vec<&str>) -> Self {
+        Self {
+            stop_words: stop_words.into_iter().map(String::from).collect(),
+        }
+    }
+}
+
+impl Step for Words {
+    fn apply(&self, input: &Seq) -> Seq {
+        let mut ret = vec![];
+        let mut i = 0;
+
+        while i < input.len() {
+            if input[i].1.is_whitespace() {
+                i += 1;
+                continue;
+            }
+
+            let len = input[i..].iter().take_while(|c| !c.1.is_whitespace()).count();
+            match text_of(&input[i..i + len]) {
+                Some(word) if self.stop_words.contains(&word) => {},
+                Some(word) => ret.push((input[i].0, Symbol::token(&word))),
+                None => ret.extend_from_slice(&input[i..i + len]), // already tokens
+            }
+            i += len;
+        }
+
+        ret
+    }
+}
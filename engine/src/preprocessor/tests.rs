@@ -1,11 +1,23 @@
 use super::*;
 use super::langs::Lang;
 
+/// Renders a sequence as a string, writing out the tokens of `keywords` by name and any other token as `<value>`
 #[cfg(test)]
-fn unsubst_keywords(s: &str, keywords: Vec<&'static str>) -> String {
-    keywords.into_iter().enumerate().fold(s.to_string(), |acc, (i, k)| {
-        acc.replace(char::from_u32((steps::identifiers::KEYWORD_OFF + i) as u32).unwrap().to_owned(), k)
-    })
+fn unsubst_keywords(seq: &Seq, keywords: Vec<&'static str>) -> String {
+    seq.iter().map(|(_, s)| match s.as_char() {
+        Some(c) => c.to_string(),
+        None => keywords.iter().find(|kw| Symbol::token(kw) == *s).map_or_else(|| format!("<{:x}>", u64::from(*s)), |kw| kw.to_string()),
+    }).collect()
+}
+
+#[cfg(test)]
+fn chars_of(seq: &Seq) -> String {
+    unsubst_keywords(seq, vec![])
+}
+
+#[cfg(test)]
+fn seq_of(chars: Vec<(usize, char)>) -> Seq {
+    chars.into_iter().map(|(i, c)| (i, c.into())).collect()
 }
 
 #[cfg(test)]
@@ -33,8 +45,8 @@ fn fib_python_chars() {
 
 print(fib(10))";
 
-    let processed_chars = apply(code, &langs::get_preprocessor(Lang::Python)).iter().map(|(_, c)| c).collect::<String>();
-    assert_eq!(unsubst_keywords(processed_chars.as_str(), langs::python::keywords()), 
+    let processed = apply(code, &langs::get_preprocessor(Lang::Python));
+    assert_eq!(unsubst_keywords(&processed, langs::python::keywords()), 
         "defI(I):ifI<=1:returnIreturnI(I-1)+I(I-2)I(I(10))");
 }
 
@@ -63,9 +75,9 @@ fn fib_struct_cpp_chars() {
         cout << f.fib(10) << endl;
     };";
 
-    let processed_chars = apply(code, &langs::get_preprocessor(Lang::Cpp)).iter().map(|(_, c)| c).collect::<String>();
-    assert_eq!(unsubst_keywords(processed_chars.as_str(), langs::cpp::keywords()), 
-        "#I<I>#I<I>usingnamespaceI;structI{II;I::I<I>I;I(II):I(I),I(I+1,-1){}II(II){if(I[I]!=-1)returnI[I];if(I<=1)returnI[I]=I;returnI[I]=I(I-1)+I(I-2);}};II(){II(10);I<<I.I(10)<<I;};");
+    let processed = apply(code, &langs::get_preprocessor(Lang::Cpp));
+    assert_eq!(unsubst_keywords(&processed, langs::cpp::keywords()), 
+        "structI{II;I::I<I>I;I(II):I(I),I(I+1,-1){}II(II){if(I[I]!=-1)returnI[I];if(I<=1)returnI[I]=I;returnI[I]=I(I-1)+I(I-2);}};II(){II(10);I<<I.I(10)<<I;};");
 }
 
 #[test]
@@ -87,9 +99,9 @@ fn fib_class_java_chars() {
         }
     }";
 
-    let processed_chars = apply(code, &langs::get_preprocessor(Lang::Java)).iter().map(|(_, c)| c).collect::<String>();
-    assert_eq!(unsubst_keywords(processed_chars.as_str(), langs::java::keywords()),
-        "importI.I.*;importI.I.*;classI{publicstaticvoidI(I[]I){I.I.I(I(10));}publicstaticII(II){if(I<=1)returnI;returnI(I-1)+I(I-2);}}");
+    let processed = apply(code, &langs::get_preprocessor(Lang::Java));
+    assert_eq!(unsubst_keywords(&processed, langs::java::keywords()),
+        "classI{publicstaticvoidI(I[]I){I.I.I(I(10));}publicstaticII(II){if(I<=1)returnI;returnI(I-1)+I(I-2);}}");
 }
 
 #[test]
@@ -98,9 +110,9 @@ fn python_indices() {
 
     let processed= apply(code, &langs::get_preprocessor(Lang::Python));
     // Output: I=5;I=6;I(f(I+
-    assert_eq!(processed, vec![
+    assert_eq!(processed, seq_of(vec![
         (0, 'I'), (2, '='), (4, '5'), (5, ';'), (7, 'I'), (9, '='), (11, '6'), (12, ';'), (24, 'I'), (29, '('), (30, 'I'), (31, '('), (32, 'I'), (34, '+')
-    ]);
+    ]));
 }
 
 #[test]
@@ -109,9 +121,9 @@ fn cpp_indices() {
 
     let processed = apply(code, &langs::get_preprocessor(Lang::Cpp));
     // Output: #IIII=5;I*I=6;I<<I(I+I)<<
-    assert_eq!(processed, vec![
+    assert_eq!(processed, seq_of(vec![
         (0, '#'), (3, 'I'), (10, 'I'), (32, 'I'), (36, 'I'), (38, '='), (40, '5'), (41, ';'), (43, 'I'), (47, '*'), (49, 'I'), (51, '='), (53, '6'), (54, ';'), (56, 'I'), (61, '<'), (62, '<'), (64, 'I'), (65, '('), (66, 'I'), (68, '+'), (70, 'I'), (71, ')'), (73, '<'), (74, '<')
-    ]);
+    ]));
 }
 
 #[test]
@@ -152,5 +164,541 @@ fn java_indices() {
     ];
 
     assert_eq!(processed.iter().map(|t| t.0).collect::<Vec<usize>>(), expect.iter().map(|t| t.0).collect::<Vec<usize>>());
-    assert!(check_match_with_holes(processed.iter().map(|t| t.1).collect(), expect.iter().map(|t| t.1).collect()));
-}
\ No newline at end of file
+    assert!(check_match_with_holes(processed.iter().map(|t| t.1).collect(), expect.iter().map(|t| t.1.map(Symbol::from)).collect()));
+}
+
+#[test]
+fn cpp_macros_match_expanded() {
+    let with_macros = "#include <bits/stdc++.h>
+    #define ll long long
+    #define FOR(i, a, b) for (int i = (a); i < (b); i++)
+    #define PB(v, x) v.push_back(x)
+    typedef vector<ll> vl;
+    using namespace std;
+
+    int main() {
+        vl v;
+        FOR(i, 0,
+            10) PB(v, (ll) i * i);
+    }";
+    let without_macros = "#include <bits/stdc++.h>
+    using namespace std;
+
+    int main() {
+        vector<long long> v;
+        for (int i = (0); i < (10); i++) v.push_back((long long) i * i);
+    }";
+
+    let pp = langs::get_preprocessor(Lang::Cpp);
+    let chars = |code| chars_of(&apply(code, &pp));
+    assert_eq!(chars(with_macros), chars(without_macros));
+}
+
+#[test]
+fn cpp_macros_indices() {
+    let code = "#define SQ(x) ((x)*(x))\n#undef SQ\n#define SQ(x) x*x\nSQ(a);";
+
+    let processed = apply(code, &vec![Box::new(steps::Macros::new(false)) as Box<dyn Step>]);
+    assert_eq!(processed, seq_of(vec![
+        (23, '\n'), (33, '\n'), (51, '\n'), (52, 'a'), (52, '*'), (52, 'a'), (57, ';')
+    ]));
+}
+
+#[test]
+fn cpp_macros_drop_directives() {
+    let code = "#include <cstdio>\n  # pragma GCC optimize(\"O3\")\n#ifdef A\n#define CAT(a, b) a ## b\n#define STR(s) #s\nCAT(x, y) STR(x \"y\")\n#endif";
+
+    let processed = chars_of(&apply(code, &vec![Box::new(steps::Macros::new(true)) as Box<dyn Step>]));
+    assert_eq!(processed, "\n\n#ifdef A\n\n\nxy \"x \\\"y\\\"\"\n#endif");
+
+    // Through the C++ preprocessor, `#pragma` lines are only dropped when asked for
+    let code = "#pragma GCC optimize(\"O3\")\nint x;";
+    let keep = chars_of(&apply(code, &langs::get_preprocessor(Lang::Cpp)));
+    let options = langs::PreprocessorOptions { drop_directives: true, ..Default::default() };
+    let drop = chars_of(&apply(code, &langs::get_preprocessor_with_options(Lang::Cpp, &options)));
+    assert!(keep.starts_with("#I"));
+    assert_eq!(drop, "II;");
+}
+
+#[test]
+fn cpp_macros_limits() {
+    let macros = |code| chars_of(&apply(code, &vec![Box::new(steps::Macros::new(false)) as Box<dyn Step>]));
+
+    // Each level doubles the expansion, so `M0` would expand to 2^40 copies of `M40`
+    let mut code = (0..40).map(|i| format!("#define M{i} M{} M{}\n", i + 1, i + 1)).collect::<String>();
+    code += "M0;\nM38;";
+    assert!(macros(&code).ends_with("\nM0;\nM40 M40 M40 M40;"));
+
+    // Alias templates take parameters, so they are not substituted like plain aliases
+    let code = "template<class T> using V = vector<T>;\nusing ll = long long;\nV<ll> v;";
+    assert_eq!(macros(code), "template<class T> using V = vector<T>;\n\nV<long long> v;");
+}
+
+#[test]
+fn boilerplate_removed() {
+    let code = "import sys; input = sys.stdin.readline
+from collections import deque
+important = int(input())
+print(important + 1)";
+
+    let processed = chars_of(&apply(code, &langs::get_preprocessor(Lang::Python)));
+    assert_eq!(processed, "I=I(I())I(I+1)");
+
+    let code = "#include <bits/stdc++.h>
+    using namespace std;
+    int main() { ios::sync_with_stdio(0); cin.tie(0);
+        ll x; cin >> x;
+    }";
+
+    let processed = apply(code, &langs::get_preprocessor_with_options(Lang::Cpp, &langs::PreprocessorOptions { boilerplate: &["ll x;"], ..Default::default() }));
+    assert_eq!(unsubst_keywords(&processed, langs::cpp::keywords()), "II(){I>>I;}");
+
+    let code = "from collections import (
+    deque,
+    defaultdict,
+)
+from math import gcd, \\
+    lcm
+x = deque()";
+
+    let processed = chars_of(&apply(code, &langs::get_preprocessor(Lang::Python)));
+    assert_eq!(processed, "I=I()");
+}
+
+#[test]
+fn notebook_cells() {
+    let code = r##"{
+        "cells": [
+            {"cell_type": "markdown", "metadata": {}, "source": ["# Lab 1\n", "Some notes"]},
+            {"cell_type": "code", "execution_count": 1, "metadata": {}, "outputs": [], "source": ["%matplotlib inline\n", "x = 5"]},
+            {"cell_type": "code", "execution_count": 2, "metadata": {}, "outputs": [{"output_type": "stream", "text": ["10\n"]}], "source": "print(x + 1)"}
+        ],
+        "metadata": {},
+        "nbformat": 4,
+        "nbformat_minor": 5
+    }"##;
+
+    let nb = notebook::Notebook::parse(code).unwrap();
+    assert_eq!(nb.source(), "\nx = 5\nprint(x + 1)");
+
+    let processed = nb.preprocess(&Default::default());
+    assert_eq!(chars_of(&processed), "I=5I(I+1)");
+    assert_eq!(processed.iter().map(|(i, _)| nb.locate(*i).unwrap()).collect::<Vec<_>>(), vec![
+        (1, 1), (1, 1), (1, 1), (2, 0), (2, 0), (2, 0), (2, 0), (2, 0), (2, 0)
+    ]);
+    assert_eq!(nb.locate(100), None);
+}
+
+#[test]
+fn keyword_tokens() {
+    // Keywords are hashed into tokens instead of being numbered, so a language can have any number of them and they stay distinct
+    let names = (0..10000).map(|i| format!("kw{}", i)).collect::<Vec<_>>();
+    let keywords = names.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    let identifiers = steps::Identifiers::new(keywords, steps::IdentifierGrammar::C_LIKE, 'I');
+    let processed = steps::Whitespace::new().apply(&identifiers.apply(&apply(&names.join(" "), &vec![])));
+    assert_eq!(processed.len(), names.len());
+    assert!(processed.iter().all(|(_, s)| s.as_char().is_none()));
+    assert_eq!(processed.iter().map(|(_, s)| *s).collect::<std::collections::HashSet<_>>().len(), names.len());
+    assert_eq!(processed[42].1, Symbol::token("kw42"));
+}
+
+#[test]
+fn notebook_preprocessor() {
+    // The notebook preprocessor applies to the joined source of the code cells, the same way as Python
+    let code = "def f(x):\n    return x + 1\n";
+    let chars = |lang| chars_of(&apply(code, &langs::get_preprocessor(lang)));
+    assert_eq!(chars(Lang::Notebook), chars(Lang::Python));
+    let canonical = langs::PreprocessorOptions { canonical: true, ..Default::default() };
+    assert_eq!(langs::get_preprocessor_with_options(Lang::Notebook, &canonical).len(), langs::get_preprocessor_with_options(Lang::Python, &canonical).len());
+}
+
+#[test]
+fn comment_multiple_and_nested() {
+    let chars = |step: steps::Comment, code: &str| chars_of(&apply(code, &vec![Box::new(step) as Box<dyn Step>]));
+
+    // Nested block comments (Rust/Swift style)
+    let rust = steps::Comment::new(vec!["//"], vec![("/*", "*/", true)]);
+    assert_eq!(chars(rust, "/* a /* b */ c */x = 1; // y\nz"), "x = 1; \nz");
+
+    // Without nesting, the first end token closes the comment
+    let c = steps::Comment::new(vec!["//"], vec![("/*", "*/", false)]);
+    assert_eq!(chars(c, "/* a /* b */ c */x"), " c */x");
+
+    // Several line tokens (PHP)
+    let php = steps::Comment::new(vec!["//", "#"], vec![("/*", "*/", false)]);
+    assert_eq!(chars(php, "# a\n$x = 1; // b\n$y"), "\n$x = 1; \n$y");
+
+    // Block token sharing a prefix with a line token (Lua)
+    let lua = steps::Comment::new(vec!["--"], vec![("--[[", "]]", false)]);
+    assert_eq!(chars(lua, "a --[[ b\nc ]] d -- e\nf"), "a  d \nf");
+}
+
+#[test]
+fn identifier_grammars() {
+    let chars = |grammar, code| chars_of(&apply(code, &vec![Box::new(steps::Identifiers::new(vec![], grammar, 'I')) as Box<dyn Step>]));
+
+    // Numbers are kept whole
+    assert_eq!(chars(steps::IdentifierGrammar::C_LIKE, "a=1e5+0x1F*1e-5-1'000ll;b.c"), "I=1e5+0x1F*1e-5-1'000ll;I.I");
+    assert_eq!(chars(steps::IdentifierGrammar::C_LIKE, "$a"), "$I");
+    assert_eq!(chars(steps::IdentifierGrammar::JAVA, "$a = a$b.c$"), "I = I.I");
+
+    let php = steps::IdentifierGrammar::new(|c| c.is_alphabetic() || c == '_' || c == '$', |c| c.is_alphanumeric() || c == '_', |_| false);
+    assert_eq!(chars(php, "$x = $y1 + z"), "I = I + I");
+
+    let lisp = steps::IdentifierGrammar::new(|c| c.is_alphabetic(), |c| c.is_alphanumeric() || c == '-', |_| false);
+    assert_eq!(chars(lisp, "(defun kebab-case (x) (- x 1))"), "(I I (I) (- I 1))");
+
+    let ruby = steps::IdentifierGrammar::new(|c| c.is_alphabetic() || c == '_', |c| c.is_alphanumeric() || c == '_', |c| c == '?' || c == '!');
+    assert_eq!(chars(ruby, "a.empty? && b.sort!(c)"), "I.I && I.I(I)");
+}
+
+#[test]
+fn text_words() {
+    let pp = langs::get_preprocessor(Lang::Text);
+    let tokens = |text| apply(text, &pp).iter().map(|(_, c)| *c).collect::<Vec<_>>();
+
+    let processed = apply("The cat, it SAT on the mat!", &pp);
+    assert_eq!(processed.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![4, 12, 23]);
+    assert_eq!(tokens("The cat, it SAT on the mat!"), tokens("cat sat mat"));
+    assert_eq!(tokens("Don't stop."), tokens("dont stop"));
+    assert_ne!(tokens("cat sat mat"), tokens("cat mat sat"));
+
+    // Every word has its own token, even in a large vocabulary
+    let vocabulary = (0..10000).map(|i| format!("w{}", i)).collect::<Vec<_>>().join(" ");
+    assert_eq!(tokens(&vocabulary).into_iter().collect::<std::collections::HashSet<_>>().len(), 10000);
+
+    let no_stop_words = langs::text::get_preprocessor_with_stop_words(vec![]);
+    assert_eq!(apply("The cat sat", &no_stop_words).len(), 3);
+
+    // Extra stop words have their own option, and boilerplate patterns do not apply to text
+    let options = langs::PreprocessorOptions { stop_words: &["cat"], boilerplate: &["sat"], ..Default::default() };
+    assert_eq!(apply("The cat sat", &langs::get_preprocessor_with_options(Lang::Text, &options)).len(), 1);
+}
+
+#[test]
+fn generic_fallback() {
+    use std::path::Path;
+
+    assert!(matches!(langs::resolve(Path::new("a/b.cpp"), b"int main() {}"), Some(Lang::Cpp)));
+    assert!(matches!(langs::resolve(Path::new("a/b.rs"), b"fn main() {}"), Some(Lang::Generic)));
+    assert!(matches!(langs::resolve(Path::new("Makefile"), b"all:\n\tcc main.c"), Some(Lang::Generic)));
+    assert!(langs::resolve(Path::new("a.out"), b"\x7fELF\x02\x01\x01\0\0\0").is_none());
+    // Binaries are usually not valid UTF-8, and text in other encodings is still text
+    assert!(langs::resolve(Path::new("Main.class"), b"\xca\xfe\xba\xbe\0\0\0\x34\x00\x1d\x0a\x00\x06").is_none());
+    assert!(langs::resolve(Path::new("image.png"), b"\x89PNG\r\n\x1a\n\x01\x02\x03\x04\x05\x06\x07\x08").is_none());
+    assert!(matches!(langs::resolve(Path::new("notes"), b"caf\xe9 = 1\n"), Some(Lang::Generic)));
+
+    let code = "fn main() { /* entry */\n    let x = 5; // five\n    # attr\n    if x > 1 { return x--; }\n}";
+    let processed = apply(code, &langs::get_preprocessor(Lang::Generic));
+    assert_eq!(unsubst_keywords(&processed, langs::generic::keywords()), "fnI(){II=5;ifI>1{returnI--;}}");
+}
+
+#[test]
+fn tokens() {
+    let pp = langs::get_preprocessor(Lang::Cpp);
+    let code = "int x = 10 <= 0x1F;  return x1 >= 2.5e-3;";
+
+    let processed = apply(code, &pp);
+    let tokenized = tokenize(&processed);
+    assert_eq!(tokenized.len(), 12);
+    assert_eq!(tokenized.iter().map(|t| t.0).collect::<Vec<_>>(), vec![0, 4, 6, 8, 11, 14, 18, 21, 28, 31, 34, 40]);
+
+    // Numbers are one token each, and the same number always maps to the same token
+    let number = |s| tokenize(&apply(s, &pp))[0].1;
+    assert_eq!(tokenized[3].1, number("10"));
+    assert_eq!(tokenized[5].1, number("0x1F"));
+    assert_eq!(tokenized[10].1, number("2.5e-3"));
+    assert_ne!(number("10"), number("11"));
+
+    // So are multi-character operators, so operator density does not change the number of tokens
+    let count = |s: &str| tokenize(&apply(s, &pp)).len();
+    assert_eq!(count("a<=b"), count("a<b"));
+    assert_eq!(count("a <= b"), count("a<b"));
+    for op in ["->", "::", "&&", "<<", "<<=", "!="] {
+        assert_eq!(count(&format!("a{}b", op)), 3);
+    }
+    assert_eq!(count("a < = b"), 4);
+    assert_ne!(tokenize(&apply("a<=b", &pp))[1].1, tokenize(&apply("a>=b", &pp))[1].1);
+
+    // Formatting does not change the tokens
+    let chars = |seq: Seq| seq.into_iter().map(|(_, c)| c).collect::<Vec<_>>();
+    assert_eq!(chars(tokenized), chars(tokenize(&apply("int   x=10<=0x1F;return x1>=2.5e-3;", &pp))));
+}
+
+#[test]
+fn segments_braces() {
+    use segments::{find_segments, SegmentKind};
+
+    let code = "#define F(x) (x)
+    struct Fib {
+        Fib(int n) : n(n), memo(n+1, -1) {}
+        int fib(int n) {
+            if (n <= 1) { return n; } // {
+            return fib(n-1) + fib(n-2);
+        }
+        bool operator<(const Fib& o) const { return n < o.n; }
+    };
+    int main() { auto f = [](int x) { return x; }; for (;;) {} puts(\"}\"); }";
+
+    let segs = find_segments(Lang::Cpp, code);
+    let names = segs.iter().map(|s| (s.kind, s.name.as_str())).collect::<Vec<_>>();
+    assert_eq!(names, vec![
+        (SegmentKind::Class, "Fib"),
+        (SegmentKind::Function, "Fib"),
+        (SegmentKind::Function, "fib"),
+        (SegmentKind::Function, "operator<"),
+        (SegmentKind::Function, "main"),
+    ]);
+    assert_eq!(&code[segs[2].start..segs[2].end], "int fib(int n) {
+            if (n <= 1) { return n; } // {
+            return fib(n-1) + fib(n-2);
+        }");
+    assert!(code[segs[0].start..segs[0].end].starts_with("struct Fib {"));
+    assert!(code[segs[0].start..segs[0].end].ends_with("return n < o.n; }\n    }"));
+
+    let code = "class Main {
+        @SuppressWarnings(\"unchecked\")
+        public static void main(String[] args) throws Exception { System.out.println(\"{\"); }
+        enum Color { RED }
+    }";
+    let names = find_segments(Lang::Java, code).into_iter().map(|s| s.name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["Main", "main", "Color"]);
+}
+
+#[test]
+fn segments_calls_with_braces() {
+    use segments::{find_segments, SegmentKind};
+
+    // Lambdas and brace-initialized arguments are not functions, but functions with qualifiers are
+    let code = "int main() {
+        sort(v.begin(), v.end(), [&](int a, int b) {
+            return a < b;
+        });
+        pq.push({d, u});
+        std::thread t([&]() { work(); });
+        if (x) { y(); }
+    }
+    struct S {
+        S() noexcept : x(0) {}
+        int get() const & noexcept(true) { return x; }
+        auto size() const -> std::size_t { return 0; }
+    };";
+
+    let segs = find_segments(Lang::Cpp, code);
+    let names = segs.iter().map(|s| (s.kind, s.name.as_str())).collect::<Vec<_>>();
+    assert_eq!(names, vec![
+        (SegmentKind::Function, "main"),
+        (SegmentKind::Class, "S"),
+        (SegmentKind::Function, "S"),
+        (SegmentKind::Function, "get"),
+        (SegmentKind::Function, "size"),
+    ]);
+    assert!(code[segs[0].start..segs[0].end].ends_with("if (x) { y(); }\n    }"));
+
+    let code = "class A {
+        void run() throws java.io.IOException, InterruptedException { list.forEach(x -> { use(x); }); }
+        void sort() { Collections.sort(list, new Comparator<Integer>() { }); }
+    }";
+    let names = find_segments(Lang::Java, code).into_iter().map(|s| s.name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["A", "run", "sort"]);
+}
+
+#[test]
+fn segments_indent() {
+    let code = "import sys
+class A:
+    def solve(self, n):
+        \"\"\"
+docstring at the wrong indentation
+        \"\"\"
+
+        return n
+
+    async def work(self):
+        pass
+def main():
+    print(A().solve(5))
+";
+
+    let segs = segments::find_segments(Lang::Python, code);
+    let names = segs.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["A", "solve", "work", "main"]);
+    assert_eq!(code.chars().skip(segs[1].start).take(segs[1].end - segs[1].start).collect::<String>(),
+        "def solve(self, n):\n        \"\"\"\ndocstring at the wrong indentation\n        \"\"\"\n\n        return n");
+    assert_eq!(&code[segs[0].start..segs[0].end], &code[11..code.find("\ndef main").unwrap()]);
+    assert!(segs[3].contains(code.find("print").unwrap()));
+    assert!(!segs[3].contains(code.find("pass").unwrap()));
+}
+
+#[test]
+fn reorder_braces() {
+    let a = "int add(int a, int b) { return a + b; }
+struct P { int x, y; };
+int g = 5;
+int main() { return add(g, 1); }";
+    let b = "int g = 5;
+int main() {
+    return sum(g, 1);
+}
+int sum(int x, int y) { return x + y; }
+struct Q { int x, y; };";
+
+    let pp = langs::get_preprocessor_with_options(Lang::Cpp, &langs::PreprocessorOptions { canonical: true, ..Default::default() });
+    let main_pos = (a.find("int main").unwrap(), b.find("int main").unwrap());
+    let (a, b) = (apply(a, &pp), apply(b, &pp));
+    assert_eq!(chars_of(&a), chars_of(&b));
+
+    // Original positions are kept: `main` is sorted first, but still points to its original place
+    assert!(chars_of(&a).starts_with("II(){"));
+    assert_eq!((a[0].0, b[0].0), main_pos);
+
+    // Other options still apply
+    let options = langs::PreprocessorOptions { canonical: true, drop_directives: true, ..Default::default() };
+    let code = "#pragma once\nint f() { x = 1; }";
+    assert_eq!(chars_of(&apply(code, &langs::get_preprocessor_with_options(Lang::Cpp, &options))), "II(){I=1;}");
+}
+
+#[test]
+fn reorder_java_members() {
+    let a = "class Main {
+    static int twice(int x) { return 2 * x; }
+    public static void main(String[] args) { System.out.println(twice(3)); }
+}";
+    let b = "class Main {
+    public static void main(String[] args) { System.out.println(twice(3)); }
+    static int twice(int x) { return 2 * x; }
+}";
+
+    let pp = langs::get_preprocessor_with_options(Lang::Java, &langs::PreprocessorOptions { canonical: true, ..Default::default() });
+    assert_eq!(chars_of(&apply(a, &pp)), chars_of(&apply(b, &pp)));
+    assert_ne!(chars_of(&apply(a, &langs::get_preprocessor(Lang::Java))), chars_of(&apply(b, &langs::get_preprocessor(Lang::Java))));
+}
+
+#[test]
+fn reorder_indentation() {
+    let a = "import sys
+@cache
+def f(n):
+    return n
+
+if f(1):
+    print(1)
+else:
+    print(
+0)
+def g():
+    pass";
+    let b = "if g(1):
+    print(1)
+else:
+    print(
+0)
+
+def h():
+    pass
+import os
+@memo
+def g(m):
+    return m";
+
+    let pp = langs::get_preprocessor_with_options(Lang::Python, &langs::PreprocessorOptions { canonical: true, ..Default::default() });
+    let processed = apply(a, &pp);
+    assert_eq!(chars_of(&processed), chars_of(&apply(b, &pp)));
+
+    let mut positions = processed.iter().map(|c| c.0).collect::<Vec<_>>();
+    positions.sort();
+    positions.dedup();
+    assert_eq!(positions, apply(a, &langs::get_preprocessor(Lang::Python)).iter().map(|c| c.0).collect::<Vec<_>>());
+}
+
+#[test]
+fn reorder_indentation_script() {
+    let a = "n = int(input())
+def f(x):
+    return x * 2
+print(f(n))
+import math
+print(math.sqrt(n))
+";
+    // Definitions can move around the script code, but the script code itself keeps its order
+    let moved = "import math
+def f(x):
+    return x * 2
+n = int(input())
+print(f(n))
+print(math.sqrt(n))
+";
+    let swapped = "import math
+def f(x):
+    return x * 2
+n = int(input())
+print(math.sqrt(n))
+print(f(n))
+";
+
+    let pp = langs::get_preprocessor_with_options(Lang::Python, &langs::PreprocessorOptions { canonical: true, ..Default::default() });
+    assert_eq!(chars_of(&apply(a, &pp)), chars_of(&apply(moved, &pp)));
+    assert_ne!(chars_of(&apply(a, &pp)), chars_of(&apply(swapped, &pp)));
+}
+
+#[cfg(feature = "ast")]
+#[test]
+fn ast_normalization() {
+    let a = "int total(int* xs, int n) {
+    int s = 0; // sum
+    for (int i = 0; i < n; i++) { s += (xs[i]); }
+    if (s > 100) { return 100; }
+    return s;
+}";
+    let b = "int sum_all(int *arr, int len)
+{
+    int acc = 0;
+    for (int j = 0; j < len; j++)
+        acc += arr[j];
+    if ((acc > 100))
+        return 100;
+    return acc;
+}";
+
+    let (pa, pb) = (ast::preprocess(Lang::Cpp, a).unwrap(), ast::preprocess(Lang::Cpp, b).unwrap());
+    assert_eq!(chars_of(&pa), chars_of(&pb));
+    assert_ne!(chars_of(&pa), chars_of(&ast::preprocess(Lang::Cpp, &a.replace("s += ", "s -= ")).unwrap()));
+
+    // Spans point back into the original document
+    let nodes = ast::linearize(Lang::Cpp, a).unwrap();
+    let decl = nodes.iter().find(|n| n.kind == "function_declarator").unwrap();
+    assert_eq!(a.chars().skip(decl.start).take(decl.end - decl.start).collect::<String>(), "total(int* xs, int n)");
+    assert!(!nodes.iter().any(|n| n.kind == "comment" || n.kind == "parenthesized_expression"));
+}
+
+#[cfg(feature = "ast")]
+#[test]
+fn ast_languages() {
+    let java = "class A { int f(int x) { return x * 2; } }";
+    let python = "def f(x):\n    return x * 2\n";
+    for (lang, code, kind) in [(Lang::Java, java, "method_declaration"), (Lang::Python, python, "function_definition")] {
+        let nodes = ast::linearize(lang, code).unwrap();
+        assert!(nodes.iter().any(|n| n.kind == kind));
+        assert!(nodes.iter().any(|n| n.kind == "*"));
+    }
+
+    assert!(ast::is_supported(Lang::Notebook));
+    assert!(ast::preprocess(Lang::Text, "hello world").is_none());
+}
+
+#[cfg(feature = "ast")]
+#[test]
+fn ast_option() {
+    let options = langs::PreprocessorOptions { ast: true, ..Default::default() };
+    let code = "x = 1\nif (x > 0):\n    print(x)\n";
+    let processed = apply(code, &langs::get_preprocessor_with_options(Lang::Python, &options));
+    assert_eq!(processed, ast::preprocess(Lang::Python, code).unwrap());
+    assert_eq!(apply(code, &langs::get_preprocessor_with_options(Lang::Notebook, &options)), processed);
+
+    // Positions come from the input, so they still refer to the original document after earlier steps
+    let shifted = code.chars().enumerate().map(|(i, c)| (i + 10, c.into())).collect::<Seq>();
+    assert!(ast::Ast::new(Lang::Python).unwrap().apply(&shifted).iter().zip(&processed).all(|(a, b)| a.0 == b.0 + 10 && a.1 == b.1));
+
+    // Languages without a grammar fall back to the char-level steps
+    assert_eq!(apply("The cat sat", &langs::get_preprocessor_with_options(Lang::Text, &options)), apply("The cat sat", &langs::get_preprocessor(Lang::Text)));
+}